@@ -0,0 +1,166 @@
+use regex::Regex;
+use std::path::Path;
+
+use crate::embedded::{self, EmbeddedBlock};
+
+pub const EXTENSIONS: [&str; 3] = ["adoc", "asciidoc", "asc"];
+
+/// The PlantUML content of an AsciiDoc document.
+pub struct AsciiDoc<'a> {
+    pub blocks: Vec<EmbeddedBlock<'a>>,
+    /// Targets of `plantuml::<target>[]` block macros, as written.
+    pub macro_targets: Vec<String>,
+}
+
+pub fn is_asciidoc(path: &Path) -> bool {
+    embedded::has_extension(path, &EXTENSIONS)
+}
+
+/// Whether `line` is a delimiter of a verbatim block, whose content is not
+/// parsed: listing (`----`), literal (`....`), comment (`////`) or
+/// passthrough (`++++`).
+fn is_verbatim_delimiter(line: &str) -> bool {
+    line.len() >= 4
+        && ['-', '.', '/', '+']
+            .iter()
+            .any(|c| line.chars().all(|l| l == *c))
+}
+
+/// Whether `line` is a delimiter of a compound block, which contains other
+/// blocks: example (`====`), sidebar (`****`), quote (`____`) or open (`--`).
+fn is_compound_delimiter(line: &str) -> bool {
+    line == "--"
+        || line.len() >= 4
+            && ['=', '*', '_']
+                .iter()
+                .any(|c| line.chars().all(|l| l == *c))
+}
+
+/// Whether the block attribute line `line` has `plantuml` as its style,
+/// e.g. `[plantuml]`, `[plantuml, target=flow, format=svg]` or
+/// `[plantuml#flow]`.
+fn is_plantuml_attribute(line: &str) -> bool {
+    let Some(attributes) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) else {
+        return false;
+    };
+    let style = attributes.split(',').next().unwrap_or("").trim();
+    let style = style.split(['#', '.', '%']).next().unwrap_or("");
+    style == "plantuml"
+}
+
+/// Finds the `[plantuml]` blocks delimited by `----` or `....` and the
+/// `plantuml::` block macros, also inside compound blocks like examples and
+/// sidebars. An unclosed block runs to the end of the document.
+pub fn parse(content: &str) -> AsciiDoc<'_> {
+    let block_macro = Regex::new(r"^plantuml::([^\[\s]+)\[.*\]$").unwrap();
+    let mut doc = AsciiDoc {
+        blocks: Vec::new(),
+        macro_targets: Vec::new(),
+    };
+    // the delimiter of the verbatim block we are inside of, with whether it
+    // is a PlantUML block
+    let mut open: Option<(&str, bool)> = None;
+    // the delimiters of the compound blocks we are inside of, innermost last
+    let mut compound: Vec<&str> = Vec::new();
+    let mut plantuml_attribute = false;
+
+    for (index, raw_line) in content.lines().enumerate() {
+        let line = raw_line.trim_end();
+
+        if let Some((delimiter, is_plantuml)) = open {
+            if line == delimiter {
+                open = None;
+            } else if is_plantuml {
+                doc.blocks.last_mut().unwrap().lines.push(raw_line);
+            }
+            continue;
+        }
+
+        if compound.last() == Some(&line) {
+            compound.pop();
+            plantuml_attribute = false;
+            continue;
+        }
+        if is_compound_delimiter(line) {
+            compound.push(line);
+            plantuml_attribute = false;
+            continue;
+        }
+
+        if is_verbatim_delimiter(line) {
+            let is_plantuml =
+                plantuml_attribute && (line.starts_with('-') || line.starts_with('.'));
+            if is_plantuml {
+                doc.blocks.push(EmbeddedBlock {
+                    opening_line: index + 1,
+                    lines: Vec::new(),
+                });
+            }
+            open = Some((line, is_plantuml));
+            plantuml_attribute = false;
+            continue;
+        }
+
+        if let Some(captures) = block_macro.captures(line) {
+            doc.macro_targets.push(captures[1].to_owned());
+        }
+
+        // a block attribute line applies to the next block, further
+        // attribute lines, titles and anchors may come in between
+        let is_title = line.starts_with('.') && !line.starts_with("..");
+        if line.starts_with('[') && line.ends_with(']') {
+            plantuml_attribute |= is_plantuml_attribute(line);
+        } else if !is_title {
+            plantuml_attribute = false;
+        }
+    }
+
+    doc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_plantuml_blocks() {
+        let content =
+            "= Doc\n\n[plantuml, target=flow, format=svg]\n.Flow\n----\nstart\n:a;\n----\n\n\
+                       [plantuml#seq]\n....\nstop\n....\n";
+        let doc = parse(content);
+        assert_eq!(doc.blocks.len(), 2);
+        assert_eq!(doc.blocks[0].opening_line, 5);
+        assert_eq!(doc.blocks[0].lines, ["start", ":a;"]);
+        assert_eq!(doc.blocks[1].opening_line, 11);
+        assert_eq!(doc.blocks[1].lines, ["stop"]);
+    }
+
+    #[test]
+    fn skips_other_blocks_and_verbatim_content() {
+        let content = "[source,java]\n----\n[plantuml]\n----\n\n[plantuml]\n:not a block;\n\
+                       ----\n:listing;\n----\n////\nplantuml::x.puml[]\n////\n";
+        let doc = parse(content);
+        assert!(doc.blocks.is_empty());
+        assert!(doc.macro_targets.is_empty());
+    }
+
+    #[test]
+    fn finds_blocks_inside_compound_blocks() {
+        let content = "====\n[plantuml]\n----\n:a;\n----\n--\n****\n[plantuml]\n....\n:b;\n....\n\
+                       ****\n--\n====\n";
+        let doc = parse(content);
+        let lines: Vec<&[&str]> = doc.blocks.iter().map(|block| &block.lines[..]).collect();
+        assert_eq!(lines, [&[":a;"][..], &[":b;"][..]]);
+    }
+
+    #[test]
+    fn collects_block_macro_targets() {
+        let content =
+            "plantuml::diagrams/flow.puml[format=svg]\n====\nplantuml::seq.puml[]\n====\n\
+                       plantuml::not a macro[]\n";
+        assert_eq!(
+            parse(content).macro_targets,
+            ["diagrams/flow.puml", "seq.puml"]
+        );
+    }
+}
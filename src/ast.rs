@@ -0,0 +1,121 @@
+//! Syntax tree of activity diagrams (new syntax).
+
+use crate::puml_validator::Span;
+
+/// A parsed `@startuml` block.
+///
+/// `starting_line` is the 1-based file line of `@startuml`, or of the line
+/// before the first diagram line for diagrams without it.
+#[derive(Clone, Debug)]
+pub struct Diagram {
+    pub starting_line: usize,
+    pub statements: Vec<Statement>,
+}
+
+/// Keywords that open, continue or close a block.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Keyword {
+    If,
+    ElseIf,
+    Else,
+    EndIf,
+    Switch,
+    Case,
+    EndSwitch,
+    Repeat,
+    RepeatWhile,
+    While,
+    EndWhile,
+    Fork,
+    ForkAgain,
+    EndFork,
+    Split,
+    SplitAgain,
+    EndSplit,
+    Partition,
+    Package,
+    Rectangle,
+    Card,
+    CloseBrace,
+    Group,
+    EndGroup,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BlockKind {
+    If,
+    Switch,
+    Repeat,
+    While,
+    Fork,
+    Split,
+    Partition,
+    Group,
+    Package,
+    Rectangle,
+    Card,
+}
+
+#[derive(Clone, Debug)]
+pub struct Statement {
+    /// The trimmed first line of the statement.
+    pub span: Span,
+    pub kind: StatementKind,
+}
+
+#[derive(Clone, Debug)]
+pub enum StatementKind {
+    /// `:text;`, or ended by one of the SDL terminators `|<>/]}` instead of
+    /// `;`. Lines of multi-line activities are joined by `\n`.
+    Action {
+        text: String,
+        terminator: char,
+    },
+    Start,
+    Stop,
+    End,
+    Kill,
+    Detach,
+    Break,
+    /// `note left: text`, or a note with its body up to `end note`.
+    /// `placement` is the text between `note` and the `:` or the end of the
+    /// line, like `left` or `over Alice`.
+    Note {
+        placement: String,
+    },
+    /// `label name`, a target of `goto`.
+    Label {
+        name: String,
+    },
+    /// `goto name`
+    Goto {
+        name: String,
+    },
+    /// A connector circle like `(A)`.
+    Connector {
+        name: String,
+    },
+    Block(Block),
+    /// Any line the parser does not model, like arrows or skinparams.
+    Other,
+}
+
+/// A control structure like `if` ... `endif`.
+///
+/// `sections[0]` is headed by the opening keyword, every further section by
+/// a continuation keyword like `else`, `case` or `fork again`. `end` is
+/// `None` if the block is not closed.
+#[derive(Clone, Debug)]
+pub struct Block {
+    pub kind: BlockKind,
+    pub sections: Vec<Section>,
+    pub end: Option<Span>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Section {
+    pub keyword: Keyword,
+    /// The trimmed line starting the section.
+    pub span: Span,
+    pub body: Vec<Statement>,
+}
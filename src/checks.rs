@@ -0,0 +1,533 @@
+//! Checks on the syntax tree of a diagram.
+
+use std::collections::HashMap;
+
+use crate::ast::{Block, BlockKind, Keyword, Section, Statement, StatementKind};
+use crate::puml_validator::{Profile, PumlErr, Rule, Span};
+
+/// Calls `visit` for every statement, including the ones nested in blocks,
+/// in source order.
+fn walk<'a>(statements: &'a [Statement], visit: &mut impl FnMut(&'a Statement)) {
+    for statement in statements {
+        visit(statement);
+        if let StatementKind::Block(block) = &statement.kind {
+            for section in block.sections.iter() {
+                walk(&section.body, visit);
+            }
+        }
+    }
+}
+
+/// Runs every check on the statements of one diagram.
+pub fn check(statements: &[Statement], profile: Profile, errors: &mut Vec<PumlErr>) {
+    let activity = is_activity(statements);
+    check_terminators(statements, errors);
+    check_else_order(statements, errors);
+    if activity {
+        check_unreachable(statements, errors);
+        check_note_placement(statements, errors);
+        check_start_stop(statements, profile, errors);
+        // sequence diagrams have `break` fragments
+        check_break(statements, false, errors);
+        check_symbols(statements, errors);
+    }
+}
+
+/// Reports activities whose text was already used with another terminator,
+/// which changes the shape they are drawn with.
+fn check_terminators(statements: &[Statement], errors: &mut Vec<PumlErr>) {
+    let mut first_use: HashMap<&str, (char, Span)> = HashMap::new();
+
+    walk(statements, &mut |statement| {
+        let StatementKind::Action { text, terminator } = &statement.kind else {
+            return;
+        };
+        let (first, first_span) = *first_use
+            .entry(text.as_str())
+            .or_insert((*terminator, statement.span));
+        if first != *terminator {
+            errors.push(
+                PumlErr::at(
+                    statement.span,
+                    Rule::InconsistentTerminator,
+                    format!(
+                        "activity ends with '{}' here but with '{}' at line {}",
+                        terminator, first, first_span.line
+                    ),
+                )
+                .with_related(first_span, format!("first ends with '{}' here", first)),
+            );
+        }
+    });
+}
+
+/// Reports `else` sections of an `if` following another `else`, and
+/// `elseif` sections following the `else`.
+fn check_else_order(statements: &[Statement], errors: &mut Vec<PumlErr>) {
+    walk(statements, &mut |statement| {
+        let StatementKind::Block(block) = &statement.kind else {
+            return;
+        };
+        if block.kind != BlockKind::If {
+            return;
+        }
+        let mut else_span: Option<Span> = None;
+        for section in block.sections[1..].iter() {
+            let (msg, first_else) = match (section.keyword, else_span) {
+                (Keyword::Else, None) => {
+                    else_span = Some(section.span);
+                    continue;
+                }
+                (Keyword::Else, Some(first)) => ("'else' appears more than once in this if", first),
+                (Keyword::ElseIf, Some(first)) => ("'elseif' after the 'else' of this if", first),
+                _ => continue,
+            };
+            errors.push(
+                PumlErr::at(section.span, Rule::ElseOrder, msg.to_owned())
+                    .with_related(first_else, "'else' is here".to_owned())
+                    .with_related(statement.span, "if opened here".to_owned()),
+            );
+        }
+    });
+}
+
+/// The keyword of statements after which a sequence does not go on.
+fn terminal_name(kind: &StatementKind) -> Option<&'static str> {
+    match kind {
+        StatementKind::Stop => Some("stop"),
+        StatementKind::End => Some("end"),
+        StatementKind::Kill => Some("kill"),
+        StatementKind::Detach => Some("detach"),
+        StatementKind::Break => Some("break"),
+        _ => None,
+    }
+}
+
+/// Reports the first statement after a terminal one in every sequence.
+/// Notes, new `start` nodes, labels, connectors and lines the parser does
+/// not model, like swimlanes, may follow.
+fn check_unreachable(statements: &[Statement], errors: &mut Vec<PumlErr>) {
+    let mut check_sequence = |sequence: &[Statement]| {
+        let mut terminal: Option<(&str, Span)> = None;
+        for statement in sequence {
+            match (&statement.kind, terminal) {
+                (
+                    StatementKind::Start
+                    | StatementKind::Label { .. }
+                    | StatementKind::Connector { .. },
+                    _,
+                ) => terminal = None,
+                (StatementKind::Note { .. } | StatementKind::Other, _) => {}
+                (_, Some((name, terminal_span))) => {
+                    errors.push(
+                        PumlErr::at(
+                            statement.span,
+                            Rule::Unreachable,
+                            format!(
+                                "unreachable after the '{}' at line {}",
+                                name, terminal_span.line
+                            ),
+                        )
+                        .with_related(terminal_span, format!("'{}' is here", name)),
+                    );
+                    break;
+                }
+                (kind, None) => {
+                    terminal = terminal_name(kind).map(|name| (name, statement.span));
+                }
+            }
+        }
+    };
+
+    check_sequence(statements);
+    walk(statements, &mut |statement| {
+        if let StatementKind::Block(block) = &statement.kind {
+            for section in block.sections.iter() {
+                check_sequence(&section.body);
+            }
+        }
+    });
+}
+
+/// Whether the diagram uses activity diagram syntax, as opposed to another
+/// kind of diagram the start and stop rules do not apply to. Groupings like
+/// `group` or `package` are shared with other diagrams and do not count.
+fn is_activity(statements: &[Statement]) -> bool {
+    let mut activity = false;
+    walk(statements, &mut |statement| {
+        activity |= match &statement.kind {
+            StatementKind::Action { .. } | StatementKind::Start => true,
+            StatementKind::Block(block) => matches!(
+                block.kind,
+                BlockKind::If
+                    | BlockKind::Switch
+                    | BlockKind::Repeat
+                    | BlockKind::While
+                    | BlockKind::Fork
+                    | BlockKind::Split
+            ),
+            _ => false,
+        };
+    });
+    activity
+}
+
+/// Note placements activity diagrams accept, besides none at all.
+const NOTE_PLACEMENTS: [&str; 2] = ["left", "right"];
+
+/// Reports notes of an activity diagram placed like in other diagrams, e.g.
+/// `note over` or `note left of`.
+fn check_note_placement(statements: &[Statement], errors: &mut Vec<PumlErr>) {
+    walk(statements, &mut |statement| {
+        let StatementKind::Note { placement } = &statement.kind else {
+            return;
+        };
+        // colors like `#pink` may follow the placement
+        let words: Vec<&str> = placement
+            .split_whitespace()
+            .filter(|word| !word.starts_with('#'))
+            .collect();
+        let valid = match words[..] {
+            [] => true,
+            [position] => NOTE_PLACEMENTS.contains(&position),
+            _ => false,
+        };
+        if !valid {
+            errors.push(PumlErr::at(
+                statement.span,
+                Rule::NotePlacement,
+                format!(
+                    "'note {}' is not valid in activity diagrams, use 'note left' or 'note right'",
+                    words.join(" ")
+                ),
+            ));
+        }
+    });
+}
+
+/// Reports diagrams without `start`, with more than one `start` and with
+/// `start` nested in a block. Under the strict profile also reports paths
+/// that reach the end of the diagram without `stop` or `end`.
+fn check_start_stop(statements: &[Statement], profile: Profile, errors: &mut Vec<PumlErr>) {
+    let mut first_start: Option<Span> = None;
+    for statement in statements {
+        if let StatementKind::Start = statement.kind {
+            match first_start {
+                None => first_start = Some(statement.span),
+                Some(first) => errors.push(
+                    PumlErr::at(
+                        statement.span,
+                        Rule::MultipleStart,
+                        format!("another 'start', the first is at line {}", first.line),
+                    )
+                    .with_related(first, "first 'start' is here".to_owned()),
+                ),
+            }
+        }
+    }
+    walk(statements, &mut |statement| {
+        let StatementKind::Block(block) = &statement.kind else {
+            return;
+        };
+        for section in block.sections.iter() {
+            for nested in section.body.iter() {
+                if let StatementKind::Start = nested.kind {
+                    errors.push(
+                        PumlErr::at(
+                            nested.span,
+                            Rule::NestedStart,
+                            "'start' nested in a block".to_owned(),
+                        )
+                        .with_related(statement.span, "the block opens here".to_owned()),
+                    );
+                }
+            }
+        }
+    });
+    if first_start.is_none() {
+        errors.push(PumlErr::at(
+            statements[0].span,
+            Rule::MissingStart,
+            "activity diagram without 'start'".to_owned(),
+        ));
+    }
+
+    if profile == Profile::Strict {
+        if let Some(span) = open_end(statements, statements[0].span) {
+            errors.push(PumlErr::at(
+                span,
+                Rule::MissingStop,
+                "a path ends here without 'stop' or 'end'".to_owned(),
+            ));
+        }
+    }
+}
+
+/// Where a path through `sequence` runs off its end, if one does: the last
+/// statement on that path, or `entry` if the path has none. Paths ending
+/// in a terminal node or leaving a loop with `break` do not.
+fn open_end(sequence: &[Statement], entry: Span) -> Option<Span> {
+    let mut last = entry;
+    for statement in sequence {
+        match &statement.kind {
+            StatementKind::Stop
+            | StatementKind::End
+            | StatementKind::Kill
+            | StatementKind::Detach
+            | StatementKind::Break => return None,
+            StatementKind::Block(block) => last = block_open_end(block, statement.span)?,
+            StatementKind::Note { .. } | StatementKind::Other => {}
+            _ => last = statement.span,
+        }
+    }
+    Some(last)
+}
+
+/// `open_end` for the paths through a block opened at `opening`.
+fn block_open_end(block: &Block, opening: Span) -> Option<Span> {
+    let after = block.end.unwrap_or(opening);
+    let first_open = |sections: &[Section]| {
+        sections
+            .iter()
+            .find_map(|section| open_end(&section.body, section.span))
+    };
+
+    match block.kind {
+        // without `else` there is a path around all branches
+        BlockKind::If => first_open(&block.sections).or_else(|| {
+            let has_else = block
+                .sections
+                .iter()
+                .any(|section| section.keyword == Keyword::Else);
+            (!has_else).then_some(after)
+        }),
+        // the lines between `switch` and the first `case` are no branch
+        BlockKind::Switch if block.sections.len() > 1 => first_open(&block.sections[1..]),
+        // loops are left once their condition fails
+        BlockKind::Repeat | BlockKind::While => Some(after),
+        _ => first_open(&block.sections),
+    }
+}
+
+/// Reports `break` outside of `repeat` and `while` loops. `in_loop` tells
+/// whether `statements` are nested in one.
+fn check_break(statements: &[Statement], in_loop: bool, errors: &mut Vec<PumlErr>) {
+    for statement in statements {
+        match &statement.kind {
+            StatementKind::Break if !in_loop => errors.push(PumlErr::at(
+                statement.span,
+                Rule::BreakOutsideLoop,
+                "'break' outside any repeat or while loop".to_owned(),
+            )),
+            StatementKind::Block(block) => {
+                let is_loop = matches!(block.kind, BlockKind::Repeat | BlockKind::While);
+                for section in block.sections.iter() {
+                    check_break(&section.body, in_loop || is_loop, errors);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The labels, gotos and connectors of a diagram by name.
+#[derive(Default)]
+struct Symbols<'a> {
+    labels: HashMap<&'a str, Span>,
+    gotos: Vec<(&'a str, Span)>,
+    connectors: HashMap<&'a str, Vec<Span>>,
+}
+
+/// Reports gotos without a label, labels defined more than once or never
+/// gone to, and connectors that appear only once, as a connector joins two
+/// places.
+fn check_symbols(statements: &[Statement], errors: &mut Vec<PumlErr>) {
+    let mut symbols = Symbols::default();
+
+    walk(statements, &mut |statement| match &statement.kind {
+        StatementKind::Label { name } => {
+            if let Some(first) = symbols.labels.get(name.as_str()) {
+                errors.push(
+                    PumlErr::at(
+                        statement.span,
+                        Rule::DuplicateLabel,
+                        format!("label '{}' is already defined at line {}", name, first.line),
+                    )
+                    .with_related(*first, format!("'{}' is first defined here", name)),
+                );
+            } else {
+                symbols.labels.insert(name, statement.span);
+            }
+        }
+        StatementKind::Goto { name } => symbols.gotos.push((name, statement.span)),
+        StatementKind::Connector { name } => symbols
+            .connectors
+            .entry(name)
+            .or_default()
+            .push(statement.span),
+        _ => {}
+    });
+
+    for (name, span) in symbols.gotos.iter() {
+        if !symbols.labels.contains_key(name) {
+            errors.push(PumlErr::at(
+                *span,
+                Rule::UndefinedLabel,
+                format!("no label '{}' to go to", name),
+            ));
+        }
+    }
+    for (name, span) in symbols.labels.iter() {
+        if !symbols.gotos.iter().any(|(goto, _)| goto == name) {
+            errors.push(PumlErr::at(
+                *span,
+                Rule::UnusedLabel,
+                format!("no goto goes to label '{}'", name),
+            ));
+        }
+    }
+    for (name, spans) in symbols.connectors.iter() {
+        if let [span] = spans[..] {
+            errors.push(PumlErr::at(
+                span,
+                Rule::UnusedConnector,
+                format!("connector '{}' is used only once", name),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+    use crate::parser::Parser;
+    use crate::puml_validator::Severity;
+
+    /// The rule and line of every error in `source`, in line order.
+    fn check_with(source: &str, profile: Profile) -> Vec<(Rule, usize)> {
+        let lines: Vec<String> = source.lines().map(str::to_owned).collect();
+        let mut errors = Vec::new();
+        let tokens = lexer::tokenize(&lexer::strip_comments(&lines), 1, &mut errors);
+        let statements = Parser::new(&tokens, &mut errors).parse();
+        check(&statements, profile, &mut errors);
+        let mut found: Vec<(Rule, usize)> =
+            errors.iter().map(|err| (err.rule, err.span.line)).collect();
+        found.sort_by_key(|(_, line)| *line);
+        found
+    }
+
+    fn check_default(source: &str) -> Vec<(Rule, usize)> {
+        check_with(source, Profile::Default)
+    }
+
+    #[test]
+    fn accepts_a_well_formed_diagram() {
+        let source = "start\n:a;\nif (x) then (y)\n  :b<\nelse\n  :c>\nendif\n\
+                      repeat\n  :d;\n  if (e) then\n    break\n  endif\nrepeat while (f)\nstop";
+        assert_eq!(check_with(source, Profile::Strict), []);
+    }
+
+    #[test]
+    fn warns_about_inconsistent_terminators() {
+        assert_eq!(
+            check_default("start\n:recv<\n:recv;\nstop"),
+            [(Rule::InconsistentTerminator, 3)]
+        );
+        assert_eq!(Rule::InconsistentTerminator.severity(), Severity::Warning);
+    }
+
+    #[test]
+    fn reports_else_order() {
+        let source = "start\nif (a) then\nelse\nelseif (b) then\nelse\nendif\nstop";
+        assert_eq!(
+            check_default(source),
+            [(Rule::ElseOrder, 4), (Rule::ElseOrder, 5)]
+        );
+    }
+
+    #[test]
+    fn checks_note_placement_in_activity_diagrams_only() {
+        assert_eq!(
+            check_default("start\nnote top: x\nnote left #pink: y\nstop"),
+            [(Rule::NotePlacement, 2)]
+        );
+        assert_eq!(
+            check_default("Alice -> Bob: hi\nnote over Alice, Bob: x\nnote left of Alice: y"),
+            []
+        );
+    }
+
+    #[test]
+    fn reports_first_unreachable_statement() {
+        let source =
+            "start\nif (a) then\n  stop\n  note right: fine\n  :dead;\n  :dead;\nendif\nstop";
+        assert_eq!(check_default(source), [(Rule::Unreachable, 5)]);
+    }
+
+    #[test]
+    fn resumes_reachability_at_labels() {
+        let source = "start\nstop\nlabel again\n:reached;\ngoto again";
+        assert_eq!(check_default(source), []);
+    }
+
+    #[test]
+    fn checks_start_nodes() {
+        assert_eq!(check_default(":a;\nstop"), [(Rule::MissingStart, 1)]);
+        assert_eq!(
+            check_default("start\nif (a) then\n  start\nendif\nstart"),
+            [(Rule::NestedStart, 3), (Rule::MultipleStart, 5)]
+        );
+    }
+
+    #[test]
+    fn reports_open_paths_in_strict_profile_only() {
+        let source = "start\nif (a) then\n  :b;\n  stop\nelse\n  :c;\nendif";
+        assert_eq!(check_default(source), []);
+        assert_eq!(
+            check_with(source, Profile::Strict),
+            [(Rule::MissingStop, 6)]
+        );
+        let closed = "start\nwhile (a)\n  :b;\nendwhile\nif (c) then\n  stop\nelse\n  end\nendif";
+        assert_eq!(check_with(closed, Profile::Strict), []);
+    }
+
+    #[test]
+    fn reports_break_outside_loops() {
+        let source = "start\nif (a) then\n  break\nendif\nwhile (b)\n  if (c) then\n    break\n  endif\nendwhile\nstop";
+        assert_eq!(check_default(source), [(Rule::BreakOutsideLoop, 3)]);
+    }
+
+    #[test]
+    fn leaves_other_diagrams_alone() {
+        let group = "Alice -> Bob: req\ngroup My label\n  Alice -> Bob: x\nend";
+        assert_eq!(check_default(group), []);
+        let alt = "alt ok\n  Bob -> Alice: y\nelse fail\n  Bob -> Alice: z\nend";
+        assert_eq!(check_default(alt), []);
+        let failure = "Alice -> Bob: req\nbreak failure\n  Bob -> Alice: err\nend";
+        assert_eq!(check_default(failure), []);
+        let fragment = "loop 3 times\n  Alice -> Bob: ping\nend\nAlice -> Bob: done";
+        assert_eq!(check_default(fragment), []);
+        let component = "package P {\n  [Component]\n}";
+        assert_eq!(check_default(component), []);
+        let use_case = "actor User\nUser --> (Login)\n(Logout)";
+        assert_eq!(check_default(use_case), []);
+        let deployment = "node server\nlabel foo";
+        assert_eq!(check_default(deployment), []);
+    }
+
+    #[test]
+    fn resolves_symbols() {
+        let source =
+            "start\nlabel a\nlabel a\nlabel unused\n(A)\n:x;\n(A)\n(B)\ngoto a\ngoto missing";
+        assert_eq!(
+            check_default(source),
+            [
+                (Rule::DuplicateLabel, 3),
+                (Rule::UnusedLabel, 4),
+                (Rule::UnusedConnector, 8),
+                (Rule::UndefinedLabel, 10),
+            ]
+        );
+    }
+}
@@ -0,0 +1,19 @@
+//! Diagrams embedded in other documents, like Markdown or AsciiDoc.
+
+use std::path::Path;
+
+/// A block of diagram source inside a document.
+///
+/// `opening_line` is the 1-based line of the opening delimiter, `lines` are
+/// the lines between the delimiters, so `lines[0]` is line `opening_line + 1`.
+pub struct EmbeddedBlock<'a> {
+    pub opening_line: usize,
+    pub lines: Vec<&'a str>,
+}
+
+/// Whether the extension of `path` is one of `extensions`, ignoring case.
+pub fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extensions.contains(&extension.to_lowercase().as_str()))
+}
@@ -0,0 +1,50 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// How the `@startuml` / `@enduml` directives of a file are unbalanced.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Error)]
+pub enum SectionErrorKind {
+    #[error("closing uml section without starting one before with @startuml")]
+    OrphanEnduml,
+    #[error("opening another uml section without closing the previous at line {previous}")]
+    NestedStartuml { previous: usize },
+    #[error("no closing @enduml found")]
+    MissingEnduml,
+}
+
+/// A problem with an input file as a whole rather than with one diagram.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("error while reading {path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    /// `line` is the 1-based file line of the offending directive and `text`
+    /// the full text of that line.
+    #[error("{path:?}:{line}: {kind}")]
+    Sections {
+        path: PathBuf,
+        line: usize,
+        text: String,
+        kind: SectionErrorKind,
+    },
+}
+
+impl Error {
+    pub fn path(&self) -> &Path {
+        match self {
+            Error::Io { path, .. } | Error::Sections { path, .. } => path,
+        }
+    }
+
+    /// The 1-based file line the error was found at, if it is tied to one.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Error::Io { .. } => None,
+            Error::Sections { line, .. } => Some(*line),
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use std::path::{Path, PathBuf};
+
+use crate::{asciidoc, embedded, markdown};
+
+/// Extensions of the PlantUML files picked up when walking a directory.
+pub const PLANTUML_EXTENSIONS: [&str; 5] = ["puml", "plantuml", "pu", "iuml", "wsd"];
+
+/// The files found for the command line paths.
+///
+/// `errors` counts directory entries that could not be walked, they are
+/// reported on stderr while walking.
+pub struct FoundFiles {
+    pub paths: Vec<PathBuf>,
+    pub errors: usize,
+}
+
+/// Selects the files to check when walking directories.
+///
+/// Without `--include` globs, files with one of the `PLANTUML_EXTENSIONS`
+/// are selected. `--include` globs narrow the selection down to the
+/// matching files among the ones the checker can read, which are PlantUML,
+/// Markdown and AsciiDoc files. `--exclude` globs take precedence.
+pub struct FileFinder {
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+}
+
+fn build_glob_set(patterns: &[String]) -> Result<Option<GlobSet>, String> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern).map_err(|e| format!("invalid glob '{}': {}", pattern, e))?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map(Some)
+        .map_err(|e| format!("invalid glob: {}", e))
+}
+
+impl FileFinder {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<FileFinder, String> {
+        Ok(FileFinder {
+            include: build_glob_set(include)?,
+            exclude: build_glob_set(exclude)?,
+        })
+    }
+
+    /// Expands directories in `paths` recursively, respecting `.gitignore`.
+    /// Paths that are not directories are passed through unchanged, so
+    /// explicitly named files are always checked.
+    pub fn find(&self, paths: &[PathBuf]) -> FoundFiles {
+        let mut found = FoundFiles {
+            paths: Vec::new(),
+            errors: 0,
+        };
+
+        for path in paths {
+            if !path.is_dir() {
+                found.paths.push(path.clone());
+                continue;
+            }
+
+            let walker = WalkBuilder::new(path)
+                .sort_by_file_name(|a, b| a.cmp(b))
+                .build();
+            for entry in walker {
+                match entry {
+                    Ok(entry) => {
+                        let is_file = entry.file_type().is_some_and(|t| t.is_file());
+                        if is_file && self.is_selected(path, entry.path()) {
+                            found.paths.push(entry.into_path());
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("error while walking {:?}: {}", path, e);
+                        found.errors += 1;
+                    }
+                }
+            }
+        }
+
+        found
+    }
+
+    /// Whether a file found below `root` should be checked. Globs are matched
+    /// against the path relative to `root`.
+    fn is_selected(&self, root: &Path, file: &Path) -> bool {
+        let relative = file.strip_prefix(root).unwrap_or(file);
+
+        if let Some(exclude) = &self.exclude {
+            if exclude.is_match(relative) {
+                return false;
+            }
+        }
+
+        let is_plantuml = embedded::has_extension(file, &PLANTUML_EXTENSIONS);
+        match &self.include {
+            Some(include) => {
+                let is_readable =
+                    is_plantuml || markdown::is_markdown(file) || asciidoc::is_asciidoc(file);
+                is_readable && include.is_match(relative)
+            }
+            None => is_plantuml,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finder(include: &[&str], exclude: &[&str]) -> FileFinder {
+        let globs = |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        FileFinder::new(&globs(include), &globs(exclude)).unwrap()
+    }
+
+    fn selected(finder: &FileFinder, file: &str) -> bool {
+        finder.is_selected(Path::new("root"), &Path::new("root").join(file))
+    }
+
+    #[test]
+    fn selects_plantuml_files_by_default() {
+        let finder = finder(&[], &[]);
+        assert!(selected(&finder, "a.puml"));
+        assert!(selected(&finder, "sub/b.WSD"));
+        assert!(!selected(&finder, "README.md"));
+        assert!(!selected(&finder, "c.png"));
+    }
+
+    #[test]
+    fn narrows_includes_to_readable_files() {
+        let finder = finder(&["docs/**"], &[]);
+        assert!(selected(&finder, "docs/a.puml"));
+        assert!(selected(&finder, "docs/guide.md"));
+        assert!(selected(&finder, "docs/guide.adoc"));
+        assert!(!selected(&finder, "docs/c.png"));
+        assert!(!selected(&finder, "a.puml"));
+    }
+
+    #[test]
+    fn lets_excludes_win() {
+        let finder = finder(&["**/*.md"], &["vendor/**"]);
+        assert!(selected(&finder, "docs/guide.md"));
+        assert!(!selected(&finder, "vendor/guide.md"));
+    }
+
+    #[test]
+    fn rejects_invalid_globs() {
+        let err = FileFinder::new(&["a[".to_owned()], &[]).err().unwrap();
+        assert!(err.starts_with("invalid glob 'a['"), "{}", err);
+    }
+}
@@ -0,0 +1,499 @@
+use regex::Regex;
+use std::sync::LazyLock;
+
+use crate::ast::Keyword;
+use crate::puml_validator::{PumlErr, Rule, Span};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    Action { text: String, terminator: char },
+    Start,
+    Stop,
+    End,
+    Kill,
+    Detach,
+    Break,
+    Note { placement: String },
+    Label { name: String },
+    Goto { name: String },
+    Connector { name: String },
+    Keyword(Keyword),
+    Other,
+}
+
+/// One non-blank diagram line, or all lines of a multi-line activity.
+#[derive(Clone, Debug)]
+pub struct Token {
+    pub kind: TokenKind,
+    /// The trimmed first line.
+    pub span: Span,
+}
+
+/// Block keywords by the pattern matching the trimmed line, in match order.
+static KEYWORDS: LazyLock<Vec<(Regex, Keyword)>> = LazyLock::new(|| {
+    [
+        // `if (x)`, optionally followed by `is (y)` or `equals (y)` and `then`
+        (r"^if\s*\(", Keyword::If),
+        (r"^(?:\([^)]*\))?\s*elseif\s*\(", Keyword::ElseIf),
+        (r"^else\b", Keyword::Else),
+        (r"^endif\b", Keyword::EndIf),
+        (r"^switch\s*\((.*?)\)", Keyword::Switch),
+        (r"^case\s*\((.*?)\)", Keyword::Case),
+        (r"^endswitch$", Keyword::EndSwitch),
+        (r"^repeat\s*while\b", Keyword::RepeatWhile),
+        (r"^repeat\b", Keyword::Repeat),
+        (r"^while\s*\((.*?)\)", Keyword::While),
+        (r"^endwhile\b", Keyword::EndWhile),
+        (r"^end\s*group\b", Keyword::EndGroup),
+        (r"^fork\s+again\b", Keyword::ForkAgain),
+        // `end fork`, `end fork {and}`, `end fork {or}` or `end merge`
+        (r"^end\s*(?:fork|merge)\s*(?:\{[^}]*\})?$", Keyword::EndFork),
+        (r"^fork\b", Keyword::Fork),
+        (r"^end\s*split\b", Keyword::EndSplit),
+        (r"^split\s+again\b", Keyword::SplitAgain),
+        (r"^split\b", Keyword::Split),
+        (r"^partition\b.*\{$", Keyword::Partition),
+        (r"^package\b.*\{$", Keyword::Package),
+        (r"^rectangle\b.*\{$", Keyword::Rectangle),
+        (r"^card\b.*\{$", Keyword::Card),
+        (r"^\}$", Keyword::CloseBrace),
+        (r"^group\b", Keyword::Group),
+    ]
+    .into_iter()
+    .map(|(pattern, keyword)| (Regex::new(pattern).unwrap(), keyword))
+    .collect()
+});
+
+/// The start of an activity: a ':', optionally after a color like `#pink`.
+static ACTIVITY: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(?:#[^:\s]+)?:").unwrap());
+
+/// `note left: text` or the opening line of a multi-line note, capturing
+/// the placement and, for single-line notes, the text.
+static NOTE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(?:floating\s+)?note\b\s*([^:]*?)\s*(:.*)?$").unwrap());
+static END_NOTE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^end\s*note$").unwrap());
+
+static LABEL: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^label\s+(\S+)$").unwrap());
+static GOTO: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^goto\s+(\S+)$").unwrap());
+/// A connector circle like `(A)`.
+static CONNECTOR: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\(([^()]+)\)$").unwrap());
+
+fn classify(line: &str) -> TokenKind {
+    match line {
+        "start" => return TokenKind::Start,
+        "stop" => return TokenKind::Stop,
+        "end" => return TokenKind::End,
+        "kill" => return TokenKind::Kill,
+        "detach" => return TokenKind::Detach,
+        "break" => return TokenKind::Break,
+        _ => {}
+    }
+    if let Some((_, keyword)) = KEYWORDS.iter().find(|(pattern, _)| pattern.is_match(line)) {
+        return TokenKind::Keyword(*keyword);
+    }
+    let name = |pattern: &Regex| {
+        pattern
+            .captures(line)
+            .map(|captures| captures[1].trim().to_owned())
+    };
+    if let Some(name) = name(&LABEL) {
+        TokenKind::Label { name }
+    } else if let Some(name) = name(&GOTO) {
+        TokenKind::Goto { name }
+    } else if let Some(name) = name(&CONNECTOR) {
+        TokenKind::Connector { name }
+    } else {
+        TokenKind::Other
+    }
+}
+
+/// Blanks out `'` line comments and `/' ... '/` block comments, which may
+/// start and end anywhere in a line. Every comment character becomes a
+/// space, so columns in the returned lines match the source.
+pub fn strip_comments(lines: &[String]) -> Vec<String> {
+    let mut in_block = false;
+
+    lines
+        .iter()
+        .map(|line| {
+            if !in_block && line.trim_start().starts_with('\'') {
+                return " ".repeat(line.chars().count());
+            }
+            let mut stripped = String::with_capacity(line.len());
+            let mut chars = line.chars().peekable();
+            while let Some(c) = chars.next() {
+                let closes = in_block && c == '\'' && chars.peek() == Some(&'/');
+                let opens = !in_block && c == '/' && chars.peek() == Some(&'\'');
+                if closes || opens {
+                    chars.next();
+                    stripped.push_str("  ");
+                    in_block = opens;
+                } else if in_block {
+                    stripped.push(' ');
+                } else {
+                    stripped.push(c);
+                }
+            }
+            stripped
+        })
+        .collect()
+}
+
+/// Characters that can end an activity: `;` and the SDL shape markers.
+pub const TERMINATORS: [char; 7] = [';', '|', '<', '>', '/', ']', '}'];
+
+/// Splits `text` into the activity text and its terminator.
+fn split_terminator(text: &str) -> Option<(&str, char)> {
+    let terminator = text.chars().next_back()?;
+    TERMINATORS
+        .contains(&terminator)
+        .then(|| (&text[..text.len() - terminator.len_utf8()], terminator))
+}
+
+/// The position right after the last character of the diagram, for notes
+/// on errors reaching the end of it.
+fn diagram_end(lines: &[String], first_line: usize) -> Span {
+    let last = lines[lines.len() - 1].chars().count();
+    Span::point(first_line + lines.len() - 1, last + 1)
+}
+
+/// The index of the `}` line closing the brace block opened on the line
+/// before `lines[from]`, if any. Nested brace blocks are skipped.
+fn brace_end(lines: &[String], from: usize) -> Option<usize> {
+    let mut depth = 1;
+    (from..lines.len()).find(|&index| {
+        let line = lines[index].trim();
+        if line.starts_with('}') {
+            depth -= 1;
+        }
+        if line.ends_with('{') {
+            depth += 1;
+        }
+        depth == 0
+    })
+}
+
+/// The index of the line ending the multi-line activity that continues at
+/// `lines[from]`, if any.
+fn activity_end(lines: &[String], from: usize) -> Option<usize> {
+    (from..lines.len()).find(|&index| lines[index].trim_end().ends_with(TERMINATORS))
+}
+
+/// Splits the lines of a diagram into tokens, one per non-blank line or
+/// multi-line activity. `first_line` is the file line of `lines[0]`.
+///
+/// Activities missing their leading ':' or trailing ';' are reported in
+/// `errors` and still become `Action` tokens. The body of a multi-line note
+/// is part of its `Note` token and not checked, like the body of a brace
+/// block that is no partition or other grouping, e.g. `skinparam ... {`.
+/// An activity not terminated before the end of the diagram becomes a
+/// single-line one, so the lines after it are still checked.
+pub fn tokenize(lines: &[String], first_line: usize, errors: &mut Vec<PumlErr>) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut index = 0;
+
+    while index < lines.len() {
+        let raw_line = &lines[index];
+        let line = raw_line.trim();
+        let span = Span::trimmed(first_line + index, raw_line);
+        index += 1;
+        if line.is_empty() {
+            continue;
+        }
+
+        let kind = if let Some(captures) = NOTE.captures(line) {
+            if captures.get(2).is_none() {
+                // the body is prose, not diagram lines
+                match (index..lines.len()).find(|&end| END_NOTE.is_match(lines[end].trim())) {
+                    Some(end) => index = end + 1,
+                    None => errors.push(
+                        PumlErr::at(
+                            span,
+                            Rule::NoteBlock,
+                            "no closing end note found".to_owned(),
+                        )
+                        .with_related(
+                            diagram_end(lines, first_line),
+                            "the diagram ends here".to_owned(),
+                        ),
+                    ),
+                }
+            }
+            TokenKind::Note {
+                placement: captures[1].to_owned(),
+            }
+        } else if END_NOTE.is_match(line) {
+            errors.push(PumlErr::at(
+                span,
+                Rule::NoteBlock,
+                "no opening note found".to_owned(),
+            ));
+            TokenKind::Other
+        } else if let Some(start) = ACTIVITY.find(line) {
+            let text = &line[start.end()..];
+            match split_terminator(text) {
+                Some((text, terminator)) => TokenKind::Action {
+                    text: text.to_owned(),
+                    terminator,
+                },
+                None => match activity_end(lines, index) {
+                    Some(end) => {
+                        let mut text = text.to_owned();
+                        for continued in lines[index..=end].iter() {
+                            text.push('\n');
+                            text.push_str(continued.trim());
+                        }
+                        let terminator = text.pop().expect("the last line ends with it");
+                        index = end + 1;
+                        TokenKind::Action { text, terminator }
+                    }
+                    None => {
+                        errors.push(
+                            PumlErr::at(
+                                span,
+                                Rule::MissingSemicolon,
+                                "activity is never terminated with ';' or an SDL terminator"
+                                    .to_owned(),
+                            )
+                            .with_related(
+                                diagram_end(lines, first_line),
+                                "the diagram ends here".to_owned(),
+                            ),
+                        );
+                        TokenKind::Action {
+                            text: text.to_owned(),
+                            terminator: ';',
+                        }
+                    }
+                },
+            }
+        } else if line.ends_with(';') && !line.contains(':') && !line.starts_with('-') {
+            // arrows like `-> label;` legitimately end with ';'
+            errors.push(PumlErr::at(
+                span,
+                Rule::MissingColon,
+                "missing ':' at the beginning of the activity".to_owned(),
+            ));
+            TokenKind::Action {
+                text: line.trim_end_matches(';').to_owned(),
+                terminator: ';',
+            }
+        } else {
+            let kind = classify(line);
+            if kind == TokenKind::Other && line.ends_with('{') {
+                // settings like `skinparam activity {` or style selectors,
+                // their body is no diagram code
+                if let Some(end) = brace_end(lines, index) {
+                    index = end + 1;
+                }
+            }
+            kind
+        };
+
+        tokens.push(Token { kind, span });
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(source: &str) -> Vec<String> {
+        source.lines().map(str::to_owned).collect()
+    }
+
+    fn kinds(source: &str) -> (Vec<TokenKind>, Vec<PumlErr>) {
+        let mut errors = Vec::new();
+        let tokens = tokenize(&strip_comments(&lines(source)), 1, &mut errors);
+        (tokens.into_iter().map(|token| token.kind).collect(), errors)
+    }
+
+    #[test]
+    fn classifies_keywords() {
+        let cases = [
+            ("if (x) then (y)", Keyword::If),
+            ("if (x) then", Keyword::If),
+            ("if (x) is (y) then", Keyword::If),
+            ("if (a) equals (b) then", Keyword::If),
+            ("if (x)", Keyword::If),
+            ("elseif (z) then (q)", Keyword::ElseIf),
+            ("(no) elseif (z) then", Keyword::ElseIf),
+            ("else (n)", Keyword::Else),
+            ("endif", Keyword::EndIf),
+            ("switch (x)", Keyword::Switch),
+            ("case (a)", Keyword::Case),
+            ("endswitch", Keyword::EndSwitch),
+            ("repeat while (more?) is (yes)", Keyword::RepeatWhile),
+            ("repeat", Keyword::Repeat),
+            ("while (x?)", Keyword::While),
+            ("endwhile", Keyword::EndWhile),
+            ("fork", Keyword::Fork),
+            ("fork again", Keyword::ForkAgain),
+            ("end fork", Keyword::EndFork),
+            ("end fork {and}", Keyword::EndFork),
+            ("end merge", Keyword::EndFork),
+            ("split", Keyword::Split),
+            ("split again", Keyword::SplitAgain),
+            ("end split", Keyword::EndSplit),
+            ("partition P {", Keyword::Partition),
+            ("package P {", Keyword::Package),
+            ("rectangle R {", Keyword::Rectangle),
+            ("card C {", Keyword::Card),
+            ("group G", Keyword::Group),
+            ("end group", Keyword::EndGroup),
+            ("}", Keyword::CloseBrace),
+        ];
+        for (line, keyword) in cases {
+            assert_eq!(classify(line), TokenKind::Keyword(keyword), "{}", line);
+        }
+    }
+
+    #[test]
+    fn classifies_nodes_and_symbols() {
+        assert_eq!(classify("stop"), TokenKind::Stop);
+        assert_eq!(classify("break"), TokenKind::Break);
+        assert_eq!(
+            classify("label top"),
+            TokenKind::Label {
+                name: "top".to_owned()
+            }
+        );
+        assert_eq!(
+            classify("goto top"),
+            TokenKind::Goto {
+                name: "top".to_owned()
+            }
+        );
+        assert_eq!(
+            classify("(A)"),
+            TokenKind::Connector {
+                name: "A".to_owned()
+            }
+        );
+        assert_eq!(classify("Alice -> Bob: hi"), TokenKind::Other);
+    }
+
+    #[test]
+    fn joins_multi_line_activities() {
+        let (kinds, errors) = kinds(":first line\nsecond line;\n:single;");
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(
+            kinds,
+            [
+                TokenKind::Action {
+                    text: "first line\nsecond line".to_owned(),
+                    terminator: ';',
+                },
+                TokenKind::Action {
+                    text: "single".to_owned(),
+                    terminator: ';',
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn joins_colored_activities() {
+        let (kinds, errors) = kinds("#pink:first line\nsecond line;\n#FF0000:single;");
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(
+            kinds,
+            [
+                TokenKind::Action {
+                    text: "first line\nsecond line".to_owned(),
+                    terminator: ';',
+                },
+                TokenKind::Action {
+                    text: "single".to_owned(),
+                    terminator: ';',
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_sdl_terminators() {
+        let (kinds, errors) = kinds(":a|\n:b<\n:c>\n:d/\n:e]\n:multi\nline}");
+        assert!(errors.is_empty(), "{:?}", errors);
+        let terminators: Vec<char> = kinds
+            .iter()
+            .map(|kind| match kind {
+                TokenKind::Action { terminator, .. } => *terminator,
+                kind => panic!("expected an activity, found {:?}", kind),
+            })
+            .collect();
+        assert_eq!(terminators, ['|', '<', '>', '/', ']', '}']);
+    }
+
+    #[test]
+    fn reports_unterminated_activity_at_its_opening_line() {
+        let (kinds, errors) = kinds(":never ends\nstop");
+        assert_eq!(kinds.len(), 2, "the lines after it are still tokenized");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].rule, Rule::MissingSemicolon);
+        assert_eq!(errors[0].span.line, 1);
+        assert_eq!(errors[0].related[0].span, Span::point(2, 5));
+    }
+
+    #[test]
+    fn reports_missing_colon() {
+        let (_, errors) = kinds("  bad;\n-> label;");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].rule, Rule::MissingColon);
+        assert_eq!(errors[0].span.column, 3);
+    }
+
+    #[test]
+    fn blanks_out_comments_keeping_columns() {
+        let stripped = strip_comments(&lines("' if (x)\n:a; /' inline\nmore '/ bad;"));
+        assert_eq!(stripped[0].trim(), "");
+        assert_eq!(stripped[1], ":a;          ");
+        assert_eq!(stripped[2], "        bad;");
+    }
+
+    #[test]
+    fn ignores_commented_out_code() {
+        let (kinds, errors) = kinds("' bad;\n/' if (x) then\n   :open '/ :a;");
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(
+            kinds,
+            [TokenKind::Action {
+                text: "a".to_owned(),
+                terminator: ';',
+            }]
+        );
+    }
+
+    #[test]
+    fn keeps_note_bodies_opaque() {
+        let (kinds, errors) = kinds("note right\n  prose; if (x) then\nend note\n:a;");
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(kinds.len(), 2);
+        assert_eq!(
+            kinds[0],
+            TokenKind::Note {
+                placement: "right".to_owned()
+            }
+        );
+    }
+
+    #[test]
+    fn reports_unbalanced_notes() {
+        let (_, errors) = kinds("end note\nnote left\n:a;");
+        let messages: Vec<&str> = errors.iter().map(|err| err.msg.as_str()).collect();
+        assert_eq!(
+            messages,
+            ["no opening note found", "no closing end note found"]
+        );
+    }
+
+    #[test]
+    fn skips_skinparam_braces() {
+        let (kinds, errors) = kinds("skinparam activity {\n  BackgroundColor white;\n}\n:a;");
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(kinds.len(), 2);
+        assert_eq!(kinds[0], TokenKind::Other);
+    }
+}
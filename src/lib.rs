@@ -0,0 +1,24 @@
+//! Checks PlantUML activity diagrams for common syntax errors.
+//!
+//! Use [`check_source`] to check diagram text and [`check_path`] to check a
+//! PlantUML, Markdown or AsciiDoc file. [`PumlValidator`] checks a set of
+//! files the way the `pumlck` binary does.
+
+mod asciidoc;
+pub mod ast;
+mod checks;
+mod embedded;
+mod error;
+pub mod file_finder;
+mod lexer;
+mod markdown;
+mod parser;
+mod puml_validator;
+pub mod report;
+
+pub use error::{Error, SectionErrorKind};
+pub use puml_validator::{
+    check_path, check_path_with_profile, check_source, check_source_with_profile, parse_source,
+    BlockDiagnostics, Diagnostic, Fix, Profile, PumlValidator, Related, Report, Rule, Severity,
+    Span, Summary, STDIN_PATH,
+};
@@ -1,17 +1,122 @@
 use std::env;
 use std::path::PathBuf;
+use std::process::ExitCode;
 
-use puml_validator::PumlValidator;
+use pumlck::file_finder::FileFinder;
+use pumlck::report::{self, Format};
+use pumlck::{Profile, PumlValidator, Summary, STDIN_PATH};
 
-mod puml_validator;
+const USAGE: &str = "usage: pumlchk [--format text|json|sarif|junit] [--profile default|strict] [--include <glob>]... [--exclude <glob>]... [--stdin-filename <name>] <file|dir|-> ...";
 
-fn main() {
-    let paths: Vec<PathBuf> = env::args().skip(1).map(PathBuf::from).collect();
-    if paths.len() == 0 {
-        println!("usage: pumlchk <file1> <file2> ...");
-        return;
+/// All diagrams were checked and no errors were found.
+const EXIT_OK: u8 = 0;
+/// At least one diagram has lint errors. Warnings alone do not fail.
+const EXIT_LINT_ERRORS: u8 = 1;
+/// At least one input could not be read or split into diagrams.
+const EXIT_INPUT_ERROR: u8 = 2;
+/// The command line could not be parsed.
+const EXIT_USAGE: u8 = 3;
+
+fn usage_error(msg: Option<String>) -> ExitCode {
+    if let Some(msg) = msg {
+        eprintln!("{}", msg);
+    }
+    eprintln!("{}", USAGE);
+    ExitCode::from(EXIT_USAGE)
+}
+
+/// The exit code of a run with `summary`, after `walk_errors` directory
+/// entries could not be walked.
+fn exit_code(summary: Summary, walk_errors: usize) -> u8 {
+    if summary.files_skipped > 0 || summary.section_errors > 0 || walk_errors > 0 {
+        EXIT_INPUT_ERROR
+    } else if summary.errors > 0 {
+        EXIT_LINT_ERRORS
+    } else {
+        EXIT_OK
+    }
+}
+
+fn main() -> ExitCode {
+    let mut format = Format::Text;
+    let mut profile = Profile::Default;
+    let mut include: Vec<String> = Vec::new();
+    let mut exclude: Vec<String> = Vec::new();
+    let mut stdin_filename: Option<PathBuf> = None;
+    let mut paths: Vec<PathBuf> = Vec::new();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" | "--profile" | "--include" | "--exclude" | "--stdin-filename" => {
+                let Some(value) = args.next() else {
+                    return usage_error(Some(format!("missing value for {}", arg)));
+                };
+                match arg.as_str() {
+                    "--format" => match value.parse() {
+                        Ok(value) => format = value,
+                        Err(e) => return usage_error(Some(e)),
+                    },
+                    "--profile" => match value.parse() {
+                        Ok(value) => profile = value,
+                        Err(e) => return usage_error(Some(e)),
+                    },
+                    "--include" => include.push(value),
+                    "--exclude" => exclude.push(value),
+                    _ => stdin_filename = Some(PathBuf::from(value)),
+                }
+            }
+            option if option.starts_with('-') && option != STDIN_PATH => {
+                return usage_error(Some(format!("unknown option '{}'", option)));
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    if paths.is_empty() {
+        return usage_error(None);
+    }
+    let finder = match FileFinder::new(&include, &exclude) {
+        Ok(finder) => finder,
+        Err(e) => return usage_error(Some(e)),
+    };
+    let found = finder.find(&paths);
+
+    let mut validator = PumlValidator::new(found.paths, stdin_filename);
+    validator.validate(profile);
+    match format {
+        Format::Text => validator.print_errors(),
+        Format::Json => report::print_json(&validator.diagnostics()),
+        Format::Sarif => report::print_sarif(&validator.diagnostics()),
+        Format::Junit => report::print_junit(&validator.reports()),
+    }
+
+    ExitCode::from(exit_code(validator.summary(), found.errors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefers_input_errors_over_lint_errors() {
+        let clean = Summary::default();
+        let lint = Summary {
+            errors: 2,
+            ..Summary::default()
+        };
+        assert_eq!(exit_code(clean, 0), EXIT_OK);
+        assert_eq!(exit_code(lint, 0), EXIT_LINT_ERRORS);
+        assert_eq!(exit_code(lint, 1), EXIT_INPUT_ERROR);
+        let skipped = Summary {
+            files_skipped: 1,
+            ..lint
+        };
+        assert_eq!(exit_code(skipped, 0), EXIT_INPUT_ERROR);
+        let unbalanced = Summary {
+            section_errors: 1,
+            ..Summary::default()
+        };
+        assert_eq!(exit_code(unbalanced, 0), EXIT_INPUT_ERROR);
     }
-    let mut validator = PumlValidator::new(paths);
-    validator.validate();
-    validator.print_errors();
 }
@@ -0,0 +1,107 @@
+use std::path::Path;
+
+use crate::embedded::{self, EmbeddedBlock};
+
+pub const EXTENSIONS: [&str; 4] = ["md", "markdown", "mdown", "mkd"];
+
+/// Info strings that mark a fenced code block as PlantUML.
+const PLANTUML_INFO_STRINGS: [&str; 2] = ["plantuml", "puml"];
+
+pub fn is_markdown(path: &Path) -> bool {
+    embedded::has_extension(path, &EXTENSIONS)
+}
+
+/// Parses an opening or closing code fence, returning the fence character,
+/// its length and the info string.
+fn parse_fence(line: &str) -> Option<(char, usize, &str)> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    if indent > 3 {
+        return None;
+    }
+    let line = &line[indent..];
+    let fence_char = line.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let length = line.len() - line.trim_start_matches(fence_char).len();
+    if length < 3 {
+        return None;
+    }
+    let info = line[length..].trim();
+    if fence_char == '`' && info.contains('`') {
+        return None;
+    }
+    Some((fence_char, length, info))
+}
+
+/// Finds all fenced code blocks whose info string starts with `plantuml` or
+/// `puml`. An unclosed fence runs to the end of the document.
+pub fn plantuml_fences(content: &str) -> Vec<EmbeddedBlock<'_>> {
+    let mut fences = Vec::new();
+    // the fence we are inside of, with whether it is a PlantUML fence
+    let mut open: Option<(char, usize, bool)> = None;
+
+    for (index, line) in content.lines().enumerate() {
+        match open {
+            None => {
+                if let Some((fence_char, length, info)) = parse_fence(line) {
+                    let language = info.split_whitespace().next().unwrap_or("");
+                    let is_plantuml = PLANTUML_INFO_STRINGS
+                        .iter()
+                        .any(|name| language.eq_ignore_ascii_case(name));
+                    if is_plantuml {
+                        fences.push(EmbeddedBlock {
+                            opening_line: index + 1,
+                            lines: Vec::new(),
+                        });
+                    }
+                    open = Some((fence_char, length, is_plantuml));
+                }
+            }
+            Some((fence_char, length, is_plantuml)) => {
+                let closes = parse_fence(line)
+                    .is_some_and(|(c, l, info)| c == fence_char && l >= length && info.is_empty());
+                if closes {
+                    open = None;
+                } else if is_plantuml {
+                    fences.last_mut().unwrap().lines.push(line);
+                }
+            }
+        }
+    }
+
+    fences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_plantuml_fences() {
+        let content = "# Title\n\n```plantuml\nstart\n:a;\n```\n\n~~~ PUML {.wide}\nstop\n~~~\n";
+        let fences = plantuml_fences(content);
+        assert_eq!(fences.len(), 2);
+        assert_eq!(fences[0].opening_line, 3);
+        assert_eq!(fences[0].lines, ["start", ":a;"]);
+        assert_eq!(fences[1].opening_line, 8);
+        assert_eq!(fences[1].lines, ["stop"]);
+    }
+
+    #[test]
+    fn skips_other_fences_and_their_content() {
+        let content = "````markdown\n```plantuml\n:nested;\n```\n````\n    ```plantuml\n";
+        assert!(plantuml_fences(content).is_empty());
+    }
+
+    #[test]
+    fn closes_fences_with_a_longer_fence_of_the_same_character() {
+        let content = "````puml\n:a;\n```\n~~~~\n`````\nafter\n";
+        let fences = plantuml_fences(content);
+        assert_eq!(fences.len(), 1);
+        assert_eq!(fences[0].lines, [":a;", "```", "~~~~"]);
+    }
+
+    #[test]
+    fn runs_an_unclosed_fence_to_the_end() {
+        let fences = plantuml_fences("text\n```plantuml\n:a;\n:b;");
+        assert_eq!(fences[0].lines, [":a;", ":b;"]);
+    }
+}
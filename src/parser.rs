@@ -0,0 +1,499 @@
+use crate::ast::{Block, BlockKind, Keyword, Section, Statement, StatementKind};
+use crate::lexer::{Token, TokenKind};
+use crate::puml_validator::{PumlErr, Rule, Span};
+
+impl BlockKind {
+    fn rule(&self) -> Rule {
+        match self {
+            BlockKind::If => Rule::IfBlock,
+            BlockKind::Switch => Rule::SwitchBlock,
+            BlockKind::Repeat => Rule::RepeatBlock,
+            BlockKind::While => Rule::WhileBlock,
+            BlockKind::Fork => Rule::ForkBlock,
+            BlockKind::Split => Rule::SplitBlock,
+            BlockKind::Partition => Rule::PartitionBlock,
+            BlockKind::Group => Rule::GroupBlock,
+            BlockKind::Package | BlockKind::Rectangle | BlockKind::Card => Rule::ContainerBlock,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            BlockKind::If => "if",
+            BlockKind::Switch => "switch",
+            BlockKind::Repeat => "repeat",
+            BlockKind::While => "while",
+            BlockKind::Fork => "fork",
+            BlockKind::Split => "split",
+            BlockKind::Partition => "partition",
+            BlockKind::Group => "group",
+            BlockKind::Package => "package",
+            BlockKind::Rectangle => "rectangle",
+            BlockKind::Card => "card",
+        }
+    }
+
+    /// How the opening line is written, for error messages.
+    fn open_text(&self) -> &'static str {
+        match self {
+            BlockKind::If => "if (*) [then (*)]",
+            BlockKind::Switch => "switch (*)",
+            BlockKind::Repeat => "repeat",
+            BlockKind::While => "while (*) [is (*)]",
+            BlockKind::Fork => "fork",
+            BlockKind::Split => "split",
+            BlockKind::Partition => "partition * {",
+            BlockKind::Group => "group",
+            BlockKind::Package => "package * {",
+            BlockKind::Rectangle => "rectangle * {",
+            BlockKind::Card => "card * {",
+        }
+    }
+
+    /// How the closing line is written, for error messages.
+    fn close_text(&self) -> &'static str {
+        match self {
+            BlockKind::If => "endif",
+            BlockKind::Switch => "endswitch",
+            BlockKind::Repeat => "repeat while (*) is (*)",
+            BlockKind::While => "endwhile [(*)]",
+            BlockKind::Fork => "end fork [{*}]|end merge",
+            BlockKind::Split => "end split",
+            BlockKind::Partition | BlockKind::Package | BlockKind::Rectangle | BlockKind::Card => {
+                "}"
+            }
+            BlockKind::Group => "end group",
+        }
+    }
+
+    fn opened_by(keyword: Keyword) -> Option<BlockKind> {
+        match keyword {
+            Keyword::If => Some(BlockKind::If),
+            Keyword::Switch => Some(BlockKind::Switch),
+            Keyword::Repeat => Some(BlockKind::Repeat),
+            Keyword::While => Some(BlockKind::While),
+            Keyword::Fork => Some(BlockKind::Fork),
+            Keyword::Split => Some(BlockKind::Split),
+            Keyword::Partition => Some(BlockKind::Partition),
+            Keyword::Group => Some(BlockKind::Group),
+            Keyword::Package => Some(BlockKind::Package),
+            Keyword::Rectangle => Some(BlockKind::Rectangle),
+            Keyword::Card => Some(BlockKind::Card),
+            _ => None,
+        }
+    }
+
+    /// Whether `keyword` starts a new section of this block.
+    fn continues_with(&self, keyword: Keyword) -> bool {
+        matches!(
+            (self, keyword),
+            (BlockKind::If, Keyword::ElseIf | Keyword::Else)
+                | (BlockKind::Switch, Keyword::Case)
+                | (BlockKind::Fork, Keyword::ForkAgain)
+                | (BlockKind::Split, Keyword::SplitAgain)
+        )
+    }
+
+    fn closed_by(&self, keyword: Keyword) -> bool {
+        matches!(
+            (self, keyword),
+            (BlockKind::If, Keyword::EndIf)
+                | (BlockKind::Switch, Keyword::EndSwitch)
+                | (BlockKind::Repeat, Keyword::RepeatWhile)
+                | (BlockKind::While, Keyword::EndWhile)
+                | (BlockKind::Fork, Keyword::EndFork)
+                | (BlockKind::Split, Keyword::EndSplit)
+                | (
+                    BlockKind::Partition
+                        | BlockKind::Package
+                        | BlockKind::Rectangle
+                        | BlockKind::Card,
+                    Keyword::CloseBrace
+                )
+                | (BlockKind::Group, Keyword::EndGroup)
+        )
+    }
+
+    fn accepts(&self, keyword: Keyword) -> bool {
+        self.continues_with(keyword) || self.closed_by(keyword)
+    }
+
+    /// The keyword separating the branches of a fork or split.
+    fn again(&self) -> Option<Keyword> {
+        match self {
+            BlockKind::Fork => Some(Keyword::ForkAgain),
+            BlockKind::Split => Some(Keyword::SplitAgain),
+            _ => None,
+        }
+    }
+
+    /// The block a continuation or closing keyword belongs to.
+    fn owning(keyword: Keyword) -> Option<BlockKind> {
+        match keyword {
+            Keyword::ElseIf | Keyword::Else | Keyword::EndIf => Some(BlockKind::If),
+            Keyword::Case | Keyword::EndSwitch => Some(BlockKind::Switch),
+            Keyword::RepeatWhile => Some(BlockKind::Repeat),
+            Keyword::EndWhile => Some(BlockKind::While),
+            Keyword::ForkAgain | Keyword::EndFork => Some(BlockKind::Fork),
+            Keyword::SplitAgain | Keyword::EndSplit => Some(BlockKind::Split),
+            Keyword::CloseBrace => Some(BlockKind::Partition),
+            Keyword::EndGroup => Some(BlockKind::Group),
+            _ => None,
+        }
+    }
+}
+
+/// Whether the tokens use activity syntax: an activity, a `start` or a
+/// control-flow block.
+fn is_activity(tokens: &[Token]) -> bool {
+    tokens.iter().any(|token| match token.kind {
+        TokenKind::Action { .. } | TokenKind::Start => true,
+        TokenKind::Keyword(keyword) => matches!(
+            BlockKind::opened_by(keyword),
+            Some(
+                BlockKind::If
+                    | BlockKind::Switch
+                    | BlockKind::Repeat
+                    | BlockKind::While
+                    | BlockKind::Fork
+                    | BlockKind::Split
+            )
+        ),
+        _ => false,
+    })
+}
+
+/// Recursive-descent parser over the tokens of one diagram.
+///
+/// A continuation or closing keyword that does not belong to the innermost
+/// open block ends every block up to the one it belongs to, each of them
+/// reported as interleaved with the locations of both openers. The later
+/// keywords of such a block, up to its closer, are skipped silently. Other
+/// keywords belonging to no open block are reported and skipped.
+///
+/// Outside activity diagrams, `group` is a sequence-diagram fragment closed
+/// by a plain `end` and `else` separates the branches of an `alt`, so
+/// neither is parsed as part of a block there.
+pub struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    activity: bool,
+    /// The blocks being parsed with the span of their opening line,
+    /// innermost last.
+    open: Vec<(BlockKind, Span)>,
+    /// Blocks reported as interleaved whose closer was not seen yet.
+    abandoned: Vec<BlockKind>,
+    errors: &'a mut Vec<PumlErr>,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(tokens: &'a [Token], errors: &'a mut Vec<PumlErr>) -> Parser<'a> {
+        Parser {
+            tokens,
+            pos: 0,
+            activity: is_activity(tokens),
+            open: Vec::new(),
+            abandoned: Vec::new(),
+            errors,
+        }
+    }
+
+    pub fn parse(mut self) -> Vec<Statement> {
+        self.parse_sequence()
+    }
+
+    /// The keyword of `token`, unless it is one only activity diagrams
+    /// have and this is not one.
+    fn keyword(&self, token: &Token) -> Option<Keyword> {
+        match token.kind {
+            TokenKind::Keyword(
+                Keyword::Group | Keyword::EndGroup | Keyword::Else | Keyword::ElseIf,
+            ) if !self.activity => None,
+            TokenKind::Keyword(keyword) => Some(keyword),
+            _ => None,
+        }
+    }
+
+    fn peek_keyword(&self) -> Option<Keyword> {
+        self.keyword(self.tokens.get(self.pos)?)
+    }
+
+    fn parse_sequence(&mut self) -> Vec<Statement> {
+        let mut statements = Vec::new();
+
+        while let Some(token) = self.tokens.get(self.pos) {
+            if let Some(keyword) = self.keyword(token) {
+                if let Some(owner) = BlockKind::owning(keyword) {
+                    if self.open.iter().any(|(open, _)| open.accepts(keyword)) {
+                        break;
+                    }
+                    if let Some(index) = self.abandoned.iter().rposition(|a| a.accepts(keyword)) {
+                        if self.abandoned[index].closed_by(keyword) {
+                            self.abandoned.remove(index);
+                        }
+                    } else {
+                        self.errors.push(self.unopened(owner, keyword, token.span));
+                    }
+                    self.pos += 1;
+                    continue;
+                }
+            }
+            statements.push(self.parse_statement());
+        }
+
+        statements
+    }
+
+    fn parse_statement(&mut self) -> Statement {
+        let tokens = self.tokens;
+        let token = &tokens[self.pos];
+        self.pos += 1;
+
+        let kind = match &token.kind {
+            TokenKind::Action { text, terminator } => StatementKind::Action {
+                text: text.clone(),
+                terminator: *terminator,
+            },
+            TokenKind::Start => StatementKind::Start,
+            TokenKind::Stop => StatementKind::Stop,
+            TokenKind::End => StatementKind::End,
+            TokenKind::Kill => StatementKind::Kill,
+            TokenKind::Detach => StatementKind::Detach,
+            TokenKind::Break => StatementKind::Break,
+            TokenKind::Note { placement } => StatementKind::Note {
+                placement: placement.clone(),
+            },
+            TokenKind::Label { name } => StatementKind::Label { name: name.clone() },
+            TokenKind::Goto { name } => StatementKind::Goto { name: name.clone() },
+            TokenKind::Connector { name } => StatementKind::Connector { name: name.clone() },
+            TokenKind::Keyword(_) => match self.keyword(token).and_then(BlockKind::opened_by) {
+                Some(kind) => StatementKind::Block(self.parse_block(kind, token)),
+                None => StatementKind::Other,
+            },
+            TokenKind::Other => StatementKind::Other,
+        };
+
+        Statement {
+            span: token.span,
+            kind,
+        }
+    }
+
+    /// Parses the sections of a block whose opening token was just consumed.
+    fn parse_block(&mut self, kind: BlockKind, opening: &Token) -> Block {
+        let TokenKind::Keyword(keyword) = opening.kind else {
+            unreachable!("blocks are opened by keywords");
+        };
+        self.open.push((kind, opening.span));
+
+        let mut block = Block {
+            kind,
+            sections: vec![Section {
+                keyword,
+                span: opening.span,
+                body: self.parse_sequence(),
+            }],
+            end: None,
+        };
+
+        loop {
+            match self.peek_keyword() {
+                Some(keyword) if kind.continues_with(keyword) => {
+                    let span = self.tokens[self.pos].span;
+                    self.pos += 1;
+                    let body = self.parse_sequence();
+                    block.sections.push(Section {
+                        keyword,
+                        span,
+                        body,
+                    });
+                }
+                Some(keyword) if kind.closed_by(keyword) => {
+                    block.end = Some(self.tokens[self.pos].span);
+                    self.pos += 1;
+                    break;
+                }
+                Some(keyword) => {
+                    // a keyword of an enclosing block
+                    self.errors
+                        .push(self.interleaving(kind, opening.span, keyword));
+                    self.abandoned.push(kind);
+                    break;
+                }
+                None => {
+                    self.errors.push(PumlErr::at(
+                        opening.span,
+                        kind.rule(),
+                        format!("no closing {} found", kind.close_text()),
+                    ));
+                    break;
+                }
+            }
+        }
+
+        self.open.pop();
+        block
+    }
+
+    /// The error for `keyword` at `span`, which belongs to an `owner` block
+    /// that is not open.
+    fn unopened(&self, owner: BlockKind, keyword: Keyword, span: Span) -> PumlErr {
+        if let Some(&(open, open_span)) = self.open.last() {
+            if owner.again() == Some(keyword) && open.again().is_some() {
+                return PumlErr::at(
+                    span,
+                    owner.rule(),
+                    format!(
+                        "'{} again' inside the {} at line {}, expected '{} again'",
+                        owner.name(),
+                        open.name(),
+                        open_span.line,
+                        open.name()
+                    ),
+                )
+                .with_related(open_span, format!("{} opened here", open.name()));
+            }
+        }
+        let msg = if owner.again() == Some(keyword) {
+            format!("'{} again' outside any {}", owner.name(), owner.name())
+        } else if keyword == Keyword::CloseBrace {
+            "no opening '{' found".to_owned()
+        } else {
+            format!("no opening {} found", owner.open_text())
+        };
+        PumlErr::at(span, owner.rule(), msg)
+    }
+
+    /// The error for the keyword at the current position, which belongs to
+    /// an enclosing block while the `kind` block opened at `opening` is
+    /// still open.
+    fn interleaving(&self, kind: BlockKind, opening: Span, keyword: Keyword) -> PumlErr {
+        let span = self.tokens[self.pos].span;
+        let (owner, owner_span) = *self
+            .open
+            .iter()
+            .rev()
+            .find(|(open, _)| open.accepts(keyword))
+            .expect("parse_sequence stops only at keywords of open blocks");
+        let verb = if owner.closed_by(keyword) {
+            "closes"
+        } else {
+            "continues"
+        };
+
+        PumlErr::at(
+            span,
+            Rule::BlockInterleaving,
+            format!(
+                "this {} the {} at line {}, but the {} at line {} is not closed yet (expected {})",
+                verb,
+                owner.name(),
+                owner_span.line,
+                kind.name(),
+                opening.line,
+                kind.close_text()
+            ),
+        )
+        .with_related(opening, format!("{} opened here", kind.name()))
+        .with_related(owner_span, format!("{} opened here", owner.name()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+
+    fn parse(source: &str) -> (Vec<Statement>, Vec<PumlErr>) {
+        let lines: Vec<String> = source.lines().map(str::to_owned).collect();
+        let mut errors = Vec::new();
+        let tokens = lexer::tokenize(&lines, 1, &mut errors);
+        let statements = Parser::new(&tokens, &mut errors).parse();
+        (statements, errors)
+    }
+
+    fn block(statement: &Statement) -> &Block {
+        match &statement.kind {
+            StatementKind::Block(block) => block,
+            kind => panic!("expected a block, found {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn parses_nested_blocks() {
+        let (statements, errors) =
+            parse("start\nif (a) then\n  while (b)\n    :x;\n  endwhile\nelse\n  stop\nendif");
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(statements.len(), 2);
+        let outer = block(&statements[1]);
+        assert_eq!(outer.kind, BlockKind::If);
+        assert_eq!(outer.sections.len(), 2);
+        assert_eq!(outer.sections[1].keyword, Keyword::Else);
+        assert_eq!(outer.end.map(|span| span.line), Some(8));
+        let inner = block(&outer.sections[0].body[0]);
+        assert_eq!(inner.kind, BlockKind::While);
+        assert_eq!(inner.sections[0].body.len(), 1);
+    }
+
+    #[test]
+    fn reports_unclosed_and_unopened_blocks() {
+        let (_, errors) = parse("endwhile\nfork\n  :a;");
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].rule, Rule::WhileBlock);
+        assert_eq!(errors[0].msg, "no opening while (*) [is (*)] found");
+        assert_eq!(errors[1].rule, Rule::ForkBlock);
+        assert_eq!(errors[1].msg, "no closing end fork [{*}]|end merge found");
+        assert_eq!(errors[1].span.line, 2);
+    }
+
+    #[test]
+    fn recovers_from_interleaved_blocks() {
+        let (statements, errors) =
+            parse("if (x) then\n  while (a)\n    :a;\n  endif\nendwhile\n:b;");
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert_eq!(errors[0].rule, Rule::BlockInterleaving);
+        assert_eq!(errors[0].span.line, 4);
+        assert_eq!(
+            errors[0].msg,
+            "this closes the if at line 1, but the while at line 2 is not closed yet \
+             (expected endwhile [(*)])"
+        );
+        let related: Vec<usize> = errors[0].related.iter().map(|r| r.span.line).collect();
+        assert_eq!(related, [2, 1]);
+        // the if ends at `endif`, the stray `endwhile` is skipped
+        assert_eq!(block(&statements[0]).end.map(|span| span.line), Some(4));
+        assert_eq!(statements.len(), 2);
+    }
+
+    #[test]
+    fn closes_group_blocks() {
+        let (statements, errors) = parse(":a;\ngroup G\n  :b;\nend group");
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(block(&statements[1]).kind, BlockKind::Group);
+        assert_eq!(block(&statements[1]).end.map(|span| span.line), Some(4));
+    }
+
+    #[test]
+    fn reports_again_keywords_of_the_other_block() {
+        let (statements, errors) = parse("split\n  :a;\nfork again\n  :b;\nend split\nsplit again");
+        let messages: Vec<&str> = errors.iter().map(|err| err.msg.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "'fork again' inside the split at line 1, expected 'split again'",
+                "'split again' outside any split",
+            ]
+        );
+        assert_eq!(block(&statements[0]).sections.len(), 1);
+    }
+
+    #[test]
+    fn closes_grouping_blocks() {
+        let (statements, errors) =
+            parse("partition P {\n  group G\n    :a;\n  end group\n}\ncard C {\n}\n}");
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert_eq!(errors[0].msg, "no opening '{' found");
+        assert_eq!(statements.len(), 2);
+        assert_eq!(block(&statements[1]).kind, BlockKind::Card);
+    }
+}
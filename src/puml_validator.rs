@@ -1,14 +1,388 @@
 use colored::Colorize;
-use regex::Regex;
+use serde::{Serialize, Serializer};
+use std::collections::{HashSet, VecDeque};
 use std::fs;
-use std::path::PathBuf;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::ast::{Diagram, Statement};
+use crate::error::{Error, SectionErrorKind};
+use crate::parser::Parser;
+use crate::{asciidoc, checks, lexer, markdown};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
 
-#[derive(Clone)]
-struct PumlErr {
-    line_number: usize,
-    msg: String,
+/// Which optional checks are run.
+///
+/// `Strict` adds checks for diagrams that are valid but likely incomplete,
+/// like paths that end without `stop`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Profile {
+    #[default]
+    Default,
+    Strict,
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Profile, String> {
+        match s {
+            "default" => Ok(Profile::Default),
+            "strict" => Ok(Profile::Strict),
+            _ => Err(format!(
+                "unknown profile '{}', expected one of: default, strict",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Rule {
+    MissingColon,
+    MissingSemicolon,
+    InconsistentTerminator,
+    IfBlock,
+    ElseOrder,
+    SwitchBlock,
+    RepeatBlock,
+    WhileBlock,
+    ForkBlock,
+    SplitBlock,
+    PartitionBlock,
+    GroupBlock,
+    ContainerBlock,
+    NoteBlock,
+    NotePlacement,
+    BlockInterleaving,
+    Unreachable,
+    MissingStart,
+    MultipleStart,
+    NestedStart,
+    MissingStop,
+    BreakOutsideLoop,
+    UndefinedLabel,
+    DuplicateLabel,
+    UnusedLabel,
+    UnusedConnector,
+    Io,
+    UmlSections,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 28] = [
+        Rule::MissingColon,
+        Rule::MissingSemicolon,
+        Rule::InconsistentTerminator,
+        Rule::IfBlock,
+        Rule::ElseOrder,
+        Rule::SwitchBlock,
+        Rule::RepeatBlock,
+        Rule::WhileBlock,
+        Rule::ForkBlock,
+        Rule::SplitBlock,
+        Rule::PartitionBlock,
+        Rule::GroupBlock,
+        Rule::ContainerBlock,
+        Rule::NoteBlock,
+        Rule::NotePlacement,
+        Rule::BlockInterleaving,
+        Rule::Unreachable,
+        Rule::MissingStart,
+        Rule::MultipleStart,
+        Rule::NestedStart,
+        Rule::MissingStop,
+        Rule::BreakOutsideLoop,
+        Rule::UndefinedLabel,
+        Rule::DuplicateLabel,
+        Rule::UnusedLabel,
+        Rule::UnusedConnector,
+        Rule::Io,
+        Rule::UmlSections,
+    ];
+
+    pub fn id(&self) -> &'static str {
+        match self {
+            Rule::MissingColon => "missing-colon",
+            Rule::MissingSemicolon => "missing-semicolon",
+            Rule::InconsistentTerminator => "inconsistent-terminator",
+            Rule::IfBlock => "if-block",
+            Rule::ElseOrder => "else-order",
+            Rule::SwitchBlock => "switch-block",
+            Rule::RepeatBlock => "repeat-block",
+            Rule::WhileBlock => "while-block",
+            Rule::ForkBlock => "fork-block",
+            Rule::SplitBlock => "split-block",
+            Rule::PartitionBlock => "partition-block",
+            Rule::GroupBlock => "group-block",
+            Rule::ContainerBlock => "container-block",
+            Rule::NoteBlock => "note-block",
+            Rule::NotePlacement => "note-placement",
+            Rule::BlockInterleaving => "block-interleaving",
+            Rule::Unreachable => "unreachable",
+            Rule::MissingStart => "missing-start",
+            Rule::MultipleStart => "multiple-start",
+            Rule::NestedStart => "nested-start",
+            Rule::MissingStop => "missing-stop",
+            Rule::BreakOutsideLoop => "break-outside-loop",
+            Rule::UndefinedLabel => "undefined-label",
+            Rule::DuplicateLabel => "duplicate-label",
+            Rule::UnusedLabel => "unused-label",
+            Rule::UnusedConnector => "unused-connector",
+            Rule::Io => "io",
+            Rule::UmlSections => "uml-sections",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Rule::MissingColon => "Activities must start with ':'.",
+            Rule::MissingSemicolon => {
+                "Activities must end with ';' or one of the SDL terminators '|<>/]}'."
+            }
+            Rule::InconsistentTerminator => {
+                "An activity repeated in a diagram must keep the same terminator."
+            }
+            Rule::IfBlock => "Every 'if' must be closed by 'endif'.",
+            Rule::ElseOrder => "An 'if' has at most one 'else', after all of its 'elseif's.",
+            Rule::SwitchBlock => "Every 'switch' must be closed by 'endswitch'.",
+            Rule::RepeatBlock => "Every 'repeat' must be closed by 'repeat while'.",
+            Rule::WhileBlock => "Every 'while' must be closed by 'endwhile'.",
+            Rule::ForkBlock => "Every 'fork' must be closed by 'end fork' or 'end merge'.",
+            Rule::SplitBlock => "Every 'split' must be closed by 'end split'.",
+            Rule::PartitionBlock => "Every 'partition {' must be closed by '}'.",
+            Rule::GroupBlock => "Every 'group' must be closed by 'end group'.",
+            Rule::ContainerBlock => {
+                "Every 'package', 'rectangle' or 'card' with '{' must be closed by '}'."
+            }
+            Rule::NoteBlock => "Every multi-line 'note' must be closed by 'end note'.",
+            Rule::NotePlacement => "Notes in activity diagrams are placed 'left' or 'right'.",
+            Rule::BlockInterleaving => {
+                "Blocks must be closed in the reverse order they were opened."
+            }
+            Rule::Unreachable => {
+                "Nothing may follow 'stop', 'end', 'kill', 'detach' or 'break' in a sequence."
+            }
+            Rule::MissingStart => "Activity diagrams should begin with 'start'.",
+            Rule::MultipleStart => "Activity diagrams should have a single 'start'.",
+            Rule::NestedStart => "'start' should not be nested in a block.",
+            Rule::MissingStop => {
+                "Every path should end with 'stop' or 'end' (strict profile only)."
+            }
+            Rule::BreakOutsideLoop => "'break' is only allowed in 'repeat' and 'while' loops.",
+            Rule::UndefinedLabel => "Every 'goto' must name a 'label' of the diagram.",
+            Rule::DuplicateLabel => "Labels must be unique within a diagram.",
+            Rule::UnusedLabel => "Labels should be the target of a 'goto'.",
+            Rule::UnusedConnector => "Connectors should appear at least twice.",
+            Rule::Io => "Input files must be readable UTF-8 text.",
+            Rule::UmlSections => "'@startuml' and '@enduml' must come in pairs.",
+        }
+    }
+
+    /// The severity of the diagnostics of this rule.
+    pub fn severity(&self) -> Severity {
+        match self {
+            Rule::InconsistentTerminator
+            | Rule::MissingStart
+            | Rule::MultipleStart
+            | Rule::NestedStart
+            | Rule::MissingStop
+            | Rule::UnusedLabel
+            | Rule::UnusedConnector => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl Serialize for Rule {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.id())
+    }
 }
 
+/// A range on a single line of a file.
+///
+/// `line` is the 1-based line in the file the diagram was read from, `column`
+/// and `end_column` are 1-based character positions with `end_column`
+/// exclusive.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub end_column: usize,
+}
+
+impl Span {
+    /// The span of the trimmed content of `source`, the text of file line
+    /// `line`.
+    pub(crate) fn trimmed(line: usize, source: &str) -> Span {
+        let column = source.chars().take_while(|c| c.is_whitespace()).count() + 1;
+        Span {
+            line,
+            column,
+            end_column: column + source.trim().chars().count(),
+        }
+    }
+
+    /// An empty span in front of `column`.
+    pub(crate) fn point(line: usize, column: usize) -> Span {
+        Span {
+            line,
+            column,
+            end_column: column,
+        }
+    }
+}
+
+/// An error found in a `Puml`.
+#[derive(Clone, Debug)]
+pub(crate) struct PumlErr {
+    pub(crate) span: Span,
+    pub(crate) rule: Rule,
+    pub(crate) msg: String,
+    pub(crate) related: Vec<Related>,
+}
+
+impl PumlErr {
+    pub(crate) fn at(span: Span, rule: Rule, msg: String) -> PumlErr {
+        PumlErr {
+            span,
+            rule,
+            msg,
+            related: Vec::new(),
+        }
+    }
+
+    /// Adds another location in the same diagram that explains the error.
+    pub(crate) fn with_related(mut self, span: Span, message: String) -> PumlErr {
+        self.related.push(Related { span, message });
+        self
+    }
+
+    /// The mechanical fix for this error, if there is one.
+    fn fix(&self) -> Option<Fix> {
+        let (column, text) = match self.rule {
+            Rule::MissingColon => (self.span.column, ":"),
+            Rule::MissingSemicolon => (self.span.end_column, ";"),
+            _ => return None,
+        };
+        Some(Fix {
+            description: format!("insert '{}'", text),
+            span: Span::point(self.span.line, column),
+            replacement: text.to_owned(),
+        })
+    }
+}
+
+/// A location in the same file that belongs to a diagnostic, like the
+/// opening line of a block that is closed wrongly.
+#[derive(Clone, Debug, Serialize)]
+pub struct Related {
+    #[serde(flatten)]
+    pub span: Span,
+    pub message: String,
+}
+
+/// A replacement of the text in `span`.
+#[derive(Clone, Debug, Serialize)]
+pub struct Fix {
+    pub description: String,
+    #[serde(flatten)]
+    pub span: Span,
+    pub replacement: String,
+}
+
+/// One reported problem, flattened for machine-readable output.
+#[derive(Clone, Debug, Serialize)]
+pub struct Diagnostic {
+    pub file: String,
+    #[serde(flatten)]
+    pub span: Span,
+    pub rule: Rule,
+    pub severity: Severity,
+    pub message: String,
+    /// The full text of the line `span` is on.
+    pub source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fix: Option<Fix>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub related: Vec<Related>,
+}
+
+/// The diagnostics of a single `@startuml` block.
+///
+/// `starting_line` is the 1-based file line of the `@startuml` directive.
+#[derive(Clone, Debug)]
+pub struct BlockDiagnostics {
+    pub starting_line: usize,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// The diagnostics of a single input file, grouped by block.
+///
+/// `file_diagnostics` are problems with the file as a whole, like a read
+/// error, which keep the file from being split into blocks.
+#[derive(Clone, Debug)]
+pub struct Report {
+    pub file: String,
+    pub file_diagnostics: Vec<Diagnostic>,
+    pub blocks: Vec<BlockDiagnostics>,
+}
+
+impl Report {
+    fn from_error(err: &Error) -> Report {
+        Report {
+            file: err.path().display().to_string(),
+            file_diagnostics: vec![error_diagnostic(err)],
+            blocks: Vec::new(),
+        }
+    }
+
+    /// All diagnostics of the file, file diagnostics first, then in block
+    /// order.
+    pub fn diagnostics(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.file_diagnostics.iter().chain(
+            self.blocks
+                .iter()
+                .flat_map(|block| block.diagnostics.iter()),
+        )
+    }
+}
+
+fn error_diagnostic(err: &Error) -> Diagnostic {
+    let (span, rule, source) = match err {
+        Error::Io { .. } => (Span::point(1, 1), Rule::Io, String::new()),
+        Error::Sections { line, text, .. } => {
+            (Span::trimmed(*line, text), Rule::UmlSections, text.clone())
+        }
+    };
+    let message = match err {
+        Error::Io { source, .. } => format!("error while reading: {}", source),
+        Error::Sections { kind, .. } => kind.to_string(),
+    };
+    Diagnostic {
+        file: err.path().display().to_string(),
+        span,
+        rule,
+        severity: Severity::Error,
+        message,
+        source,
+        fix: None,
+        related: Vec::new(),
+    }
+}
+
+/// One `@startuml` ... `@enduml` block.
+///
+/// `starting_line` is the 1-based file line of `@startuml`, so `lines[0]` is
+/// file line `starting_line + 1`.
 #[derive(Clone)]
 struct Puml {
     starting_line: usize,
@@ -24,300 +398,560 @@ impl Puml {
             errors: Vec::new(),
         }
     }
-    fn validate(&mut self) {
-
-        // validate missing :
-        self.validate_pattern(
-            r"[^;]*?;$",
-            r":[^;]*?;",
-            "missing ':' at the beginning of the line (doesnt check multiline)",
-        );
-
-        // validate missing ;
-        self.validate_pattern(
-            r":[^;]*?",
-            r":[^;]*?;$",
-            "missing ';' at the end of the line (doesnt check multiline)",
-        );
-
-        // validate if else endif
-        self.validate_open_close(
-            r"^if\s*\([^)]*\)\s*then\s*\([^)]*\)",
-            Some(r"^(?:else\s*\([^)]*\)|(?:\([^)]*\))?\s*elseif\s*(?:\([^)]*\))?\s*then\s*(?:\([^)]*\))?)"),
-            r"^endif",
-            r"if (*) then (*)",
-            "endif",
-        );
-
-        // validate switch
-        self.validate_open_close(
-            r"^switch\s*\((.*?)\)",
-            Some(r"^case\s*\((.*?)\)"),
-            r"^endswitch$",
-            r"switch (*)",
-            "endswitch",
-        );
-
-        // validate repeat while
-        self.validate_open_close(
-            r"^repeat\b",
-            None,
-            r"^repeat\s+while\s*\((.*?)\)\s+is\s+(.*)",
-            r"repeat",
-            "repeat while (*) is (*)",
-        );
+    /// Tokenizes and parses the diagram, pushing syntax errors to `errors`.
+    fn parse(&self, errors: &mut Vec<PumlErr>) -> Vec<Statement> {
+        let lines = lexer::strip_comments(&self.lines);
+        let tokens = lexer::tokenize(&lines, self.file_line(0), errors);
+        Parser::new(&tokens, errors).parse()
+    }
 
-        // validate while
-        self.validate_open_close(
-            r"^while\s*\((.*?)\)",
-            None,
-            r"^endwhile\s*\((.*?)\)",
-            r"while (*) [is (*)]",
-            "endwhile [(*)]",
-        );
+    fn validate(&mut self, profile: Profile) {
+        let mut errors = Vec::new();
+        let statements = self.parse(&mut errors);
+        checks::check(&statements, profile, &mut errors);
+        self.errors = errors;
 
-        // validate fork
-        self.validate_open_close(
-            r"^fork",
-            Some(r"^fork again$"),
-            r"^end fork|^end merge",
-            r"fork",
-            "end fork|end merge",
-        );
+        self.errors
+            .sort_by_key(|err| (err.span.line, err.span.column));
+    }
 
-        // validate split
-        self.validate_open_close(
-            r"^split",
-            Some(r"^fork again$"),
-            r"^end split",
-            "split",
-            "end split",
-        );
+    /// The file line of `lines[index]`.
+    fn file_line(&self, index: usize) -> usize {
+        self.starting_line + 1 + index
+    }
 
-        self.errors
-            .sort_by(|a, b| a.line_number.cmp(&b.line_number));
+    /// The text of file line `line`, which must belong to this block.
+    fn source_line(&self, line: usize) -> &str {
+        &self.lines[line - self.starting_line - 1]
     }
 
-    fn print_errors(&self) {
+    fn print_errors(&self, file: &str) {
         println!();
         println!("PlantUML starting at line {}:", self.starting_line);
-        if self.errors.len() == 0 {
+        if self.errors.is_empty() {
             println!("OK!");
         }
-        for PumlErr { line_number, msg } in self.errors.iter() {
-            println!(
-                "{}: {} {}",
-                line_number.to_string().color("grey"),
-                self.lines[*line_number].bold(),
-                msg.red()
-            );
+        for diagnostic in self.diagnostics(file) {
+            print_diagnostic(&diagnostic, "");
         }
         println!();
     }
 
-    fn validate_pattern(&mut self, simple_pattern: &str, validation_pattern: &str, msg: &str) {
-        let simple_pattern = Regex::new(simple_pattern).unwrap();
-        let validation_pattern = Regex::new(validation_pattern).unwrap();
-        for (line_number, line) in self.lines.iter().enumerate() {
-            let line = line.trim();
-            if simple_pattern.is_match(line) {
-                if !validation_pattern.is_match(line) {
-                    self.errors.push(PumlErr {
-                        line_number,
-                        msg: format!("<- {}", msg),
-                    })
-                }
-            }
-        }
+    fn diagnostics(&self, file: &str) -> Vec<Diagnostic> {
+        self.errors
+            .iter()
+            .map(|err| Diagnostic {
+                file: file.to_owned(),
+                span: err.span,
+                rule: err.rule,
+                severity: err.rule.severity(),
+                message: err.msg.clone(),
+                source: self.source_line(err.span.line).to_owned(),
+                fix: err.fix(),
+                related: err.related.clone(),
+            })
+            .collect()
     }
+}
 
-    fn validate_open_close(
-        &mut self,
-        open: &str,
-        middle: Option<&str>,
-        close: &str,
-        open_text: &str,
-        close_text: &str,
-    ) {
-        let open = Regex::new(open).unwrap();
-        let middle = match middle {
-            Some(str) => Some(Regex::new(str).unwrap()),
-            None => None,
-        };
-        let close = Regex::new(close).unwrap();
-        let mut opening_stack: Vec<usize> = Vec::new();
-
-        for (line_number, line) in self.lines.iter().enumerate() {
-            let line = line.trim();
-            if open.is_match(line) {
-                opening_stack.push(line_number);
-                continue;
-            }
+struct PumlFile {
+    path: PathBuf,
+    filename: String,
+    pumls: Vec<Puml>,
+    /// Unbalanced `@startuml` / `@enduml` directives, the sections were
+    /// split as well as possible regardless.
+    section_errors: Vec<Error>,
+    /// Other files this file pulls diagrams from, which are checked too.
+    referenced_files: Vec<PathBuf>,
+}
 
-            if let Some(middle) = &middle {
-                if middle.is_match(line) {
-                    if opening_stack.is_empty() {
-                        self.errors.push(PumlErr {
-                            line_number,
-                            msg: format!("<- no opening {} found", open_text),
-                        });
-                    }
-                    continue;
-                }
+/// Splits numbered lines into `@startuml` ... `@enduml` sections.
+///
+/// `lines` yields the 1-based file line number with the text of every line.
+/// Unbalanced directives are pushed to `errors` and the split resyncs: an
+/// orphan `@enduml` is ignored, a nested `@startuml` implicitly closes the
+/// open section and a section still open at the end is kept.
+fn read_pumls<'a>(
+    path: &Path,
+    lines: impl Iterator<Item = (usize, &'a str)>,
+    errors: &mut Vec<Error>,
+) -> Vec<Puml> {
+    let mut reading_uml = false;
+    let mut pumls = Vec::new();
+    let mut puml_buffer = Puml::new();
+    let mut starting_text = "";
+
+    let section_error = |line, text: &str, kind| Error::Sections {
+        path: path.to_path_buf(),
+        line,
+        text: text.to_owned(),
+        kind,
+    };
+
+    for (line_number, raw_line) in lines {
+        let line = raw_line.trim();
+
+        // check if the current lines are part of an uml
+        if line.starts_with("@startuml") {
+            if reading_uml {
+                // we are already reading an open uml section, close it
+                errors.push(section_error(
+                    line_number,
+                    raw_line,
+                    SectionErrorKind::NestedStartuml {
+                        previous: puml_buffer.starting_line,
+                    },
+                ));
+                pumls.push(puml_buffer);
+                puml_buffer = Puml::new();
             }
+            reading_uml = true;
+            puml_buffer.starting_line = line_number;
+            starting_text = raw_line;
+            continue;
+        }
 
-            if close.is_match(line) {
-                match opening_stack.pop() {
-                    Some(_) => {}
-                    None => {
-                        self.errors.push(PumlErr {
-                            line_number,
-                            msg: format!("<- no opening {} found", open_text),
-                        });
-                    }
-                }
-                continue;
+        if line.starts_with("@enduml") {
+            if reading_uml {
+                reading_uml = false;
+                pumls.push(puml_buffer);
+                puml_buffer = Puml::new();
+            } else {
+                // we are not reading an open uml section, ignore the directive
+                errors.push(section_error(
+                    line_number,
+                    raw_line,
+                    SectionErrorKind::OrphanEnduml,
+                ));
             }
+            continue;
         }
 
-        for line_number in opening_stack {
-            self.errors.push(PumlErr {
-                line_number,
-                msg: format!("<- no closing {} found", close_text),
-            });
+        // read lines belonging to an uml into the buffer
+        if reading_uml {
+            puml_buffer.lines.push(raw_line.to_string());
         }
     }
+
+    if reading_uml {
+        errors.push(section_error(
+            puml_buffer.starting_line,
+            starting_text,
+            SectionErrorKind::MissingEnduml,
+        ));
+        pumls.push(puml_buffer);
+    }
+
+    pumls
 }
 
-struct PumlFile {
-    filename: String,
-    pumls: Vec<Puml>,
+/// Reads a diagram embedded in a document, e.g. a Markdown fence. Blocks
+/// containing `@startuml` are split like a PlantUML file, all others are a
+/// single diagram starting at `opening_line`, the line before `lines[0]`.
+fn read_embedded_pumls(
+    path: &Path,
+    opening_line: usize,
+    lines: &[&str],
+    errors: &mut Vec<Error>,
+) -> Vec<Puml> {
+    if lines
+        .iter()
+        .any(|line| line.trim().starts_with("@startuml"))
+    {
+        let numbered_lines = lines
+            .iter()
+            .enumerate()
+            .map(|(index, line)| (opening_line + 1 + index, *line));
+        read_pumls(path, numbered_lines, errors)
+    } else {
+        vec![Puml {
+            starting_line: opening_line,
+            lines: lines.iter().map(|line| line.to_string()).collect(),
+            errors: Vec::new(),
+        }]
+    }
+}
+
+fn read_markdown_pumls(path: &Path, content: &str, errors: &mut Vec<Error>) -> Vec<Puml> {
+    markdown::plantuml_fences(content)
+        .iter()
+        .flat_map(|fence| read_embedded_pumls(path, fence.opening_line, &fence.lines, errors))
+        .collect()
+}
+
+/// Reads the `[plantuml]` blocks of an AsciiDoc document, together with the
+/// files referenced by `plantuml::` block macros, resolved relative to the
+/// document.
+fn read_asciidoc_pumls(
+    path: &Path,
+    content: &str,
+    errors: &mut Vec<Error>,
+) -> (Vec<Puml>, Vec<PathBuf>) {
+    let doc = asciidoc::parse(content);
+    let pumls = doc
+        .blocks
+        .iter()
+        .flat_map(|block| read_embedded_pumls(path, block.opening_line, &block.lines, errors))
+        .collect();
+
+    let base = path.parent().unwrap_or(Path::new(""));
+    let referenced_files = doc
+        .macro_targets
+        .iter()
+        // attribute references like {diagramsdir} can't be resolved here
+        .filter(|target| !target.contains('{'))
+        .map(|target| base.join(target))
+        .collect();
+    (pumls, referenced_files)
+}
+
+/// The last component of `path`, used to head the text output of a file.
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
 }
 
 impl PumlFile {
-    fn new(path: &PathBuf) -> Option<PumlFile> {
-        match fs::read_to_string(path) {
-            Ok(content) => {
-                let mut reading_uml = false;
-
-                let mut puml_file = PumlFile {
-                    filename: path.file_name().unwrap().to_str().unwrap().to_owned(),
-                    pumls: Vec::new(),
-                };
-
-                let mut puml_buffer = Puml {
-                    starting_line: 0,
-                    lines: Vec::new(),
-                    errors: Vec::new(),
-                };
-
-                for (line_number, line) in content.lines().enumerate() {
-                    let line = line.trim();
-
-                    if line.starts_with("@enduml") {
-                        if reading_uml {
-                            reading_uml = false;
-                            puml_file.pumls.push(puml_buffer.clone());
-                            puml_buffer = Puml::new();
-                        } else {
-                            // error because we are not reading an open uml section
-                            eprintln!();
-                            eprintln!(
-                                "skipping file {:?} because of error while parsing uml sections!",
-                                path
-                            );
-                            eprintln!(
-                                "{}: {}  <- closing uml section without starting one before with @startuml",
-                                line_number, line
-                            );
-                            eprintln!();
-                            return None;
-                        }
-                    }
-
-                    // read lines belonging to an uml into the buffer
-                    if reading_uml {
-                        puml_buffer.lines.push(line.to_string());
-                    }
-
-                    // check if the current lines are part of an uml
-                    if line.starts_with("@startuml") {
-                        if !reading_uml {
-                            reading_uml = true;
-                            puml_buffer.starting_line = line_number;
-                        } else {
-                            // error because we are already reading an open uml section
-                            eprintln!();
-                            eprintln!(
-                                "skipping file {:?} because of error while parsing uml sections!",
-                                path
-                            );
-                            eprintln!(
-                                "{}: @startuml... <- opening uml section",
-                                puml_buffer.starting_line
-                            );
-                            eprintln!(
-                                "{}: {}  <- opening another uml section without closing the previous at line {}",
-                                line_number, line, puml_buffer.starting_line
-                            );
-                            eprintln!();
-                            return None;
-                        }
-                    }
-                }
-
-                Some(puml_file)
-            }
-            Err(e) => {
-                eprintln!("ignoring file {:?}, error while reading: {}", path, e);
-                None
-            }
+    fn new(path: &Path) -> Result<PumlFile, Error> {
+        let file = fs::File::open(path).map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        PumlFile::from_reader(path, file)
+    }
+
+    /// Reads the whole of `reader` and parses it as the content of `path`.
+    fn from_reader(path: &Path, mut reader: impl Read) -> Result<PumlFile, Error> {
+        let mut content = String::new();
+        reader
+            .read_to_string(&mut content)
+            .map_err(|source| Error::Io {
+                path: path.to_path_buf(),
+                source,
+            })?;
+        Ok(PumlFile::from_source(path, &content))
+    }
+
+    /// Parses `content`, using `path` to name the file in reports, to pick
+    /// the document kind by extension and to resolve referenced files.
+    fn from_source(path: &Path, content: &str) -> PumlFile {
+        let mut referenced_files = Vec::new();
+        let mut section_errors = Vec::new();
+        let pumls = if markdown::is_markdown(path) {
+            read_markdown_pumls(path, content, &mut section_errors)
+        } else if asciidoc::is_asciidoc(path) {
+            let (pumls, references) = read_asciidoc_pumls(path, content, &mut section_errors);
+            referenced_files = references;
+            pumls
+        } else {
+            let lines = content.lines().enumerate().map(|(i, l)| (i + 1, l));
+            read_pumls(path, lines, &mut section_errors)
+        };
+
+        PumlFile {
+            path: path.to_path_buf(),
+            filename: file_name(path),
+            pumls,
+            section_errors,
+            referenced_files,
         }
     }
 
-    fn validate(&mut self) {
+    fn validate(&mut self, profile: Profile) {
         for puml in self.pumls.iter_mut() {
-            puml.validate();
+            puml.validate(profile);
+        }
+    }
+
+    fn report(&self) -> Report {
+        let file = self.path.display().to_string();
+        Report {
+            file_diagnostics: self.section_errors.iter().map(error_diagnostic).collect(),
+            blocks: self
+                .pumls
+                .iter()
+                .map(|puml| BlockDiagnostics {
+                    starting_line: puml.starting_line,
+                    diagnostics: puml.diagnostics(&file),
+                })
+                .collect(),
+            file,
         }
     }
 
     fn print_errors(&self) {
         println!("In file {}:", self.filename);
+        let file = self.path.display().to_string();
+        if !self.section_errors.is_empty() {
+            println!();
+        }
+        for err in self.section_errors.iter() {
+            print_diagnostic(&error_diagnostic(err), "");
+        }
         for puml in self.pumls.iter() {
-            puml.print_errors();
+            puml.print_errors(&file);
         }
     }
 }
 
+/// The path argument that reads from stdin.
+pub const STDIN_PATH: &str = "-";
+
+/// Totals over all validated files, used to pick the process exit code.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Summary {
+    pub files_skipped: usize,
+    /// Unbalanced `@startuml` and `@enduml` lines, which keep a file from
+    /// being split into diagrams.
+    pub section_errors: usize,
+    pub errors: usize,
+}
+
 pub struct PumlValidator {
-    puml_files: Vec<PumlFile>,
+    /// The given and referenced files in the order they were read, with the
+    /// error for every file that could not be read.
+    puml_files: Vec<Result<PumlFile, Error>>,
 }
 
 impl PumlValidator {
-    pub fn new(files: Vec<PathBuf>) -> PumlValidator {
+    /// Reads `files`, where `-` stands for stdin. `stdin_filename` names the
+    /// stdin input in reports and defaults to `<stdin>`.
+    pub fn new(files: Vec<PathBuf>, stdin_filename: Option<PathBuf>) -> PumlValidator {
         let mut validator = PumlValidator {
             puml_files: Vec::new(),
         };
 
-        for file in files.iter() {
-            match PumlFile::new(file) {
-                Some(puml_file) => {
-                    validator.puml_files.push(puml_file);
-                }
-                None => {}
+        // files referenced from documents are queued behind the given ones,
+        // each file is read once no matter how often it is referenced
+        let mut queue: VecDeque<PathBuf> = files.into_iter().collect();
+        let mut seen: HashSet<PathBuf> = HashSet::new();
+        while let Some(file) = queue.pop_front() {
+            if !seen.insert(fs::canonicalize(&file).unwrap_or(file.clone())) {
+                continue;
+            }
+            let puml_file = if file.as_os_str() == STDIN_PATH {
+                let name = stdin_filename
+                    .clone()
+                    .unwrap_or_else(|| PathBuf::from("<stdin>"));
+                PumlFile::from_reader(&name, io::stdin())
+            } else {
+                PumlFile::new(&file)
+            };
+            if let Ok(puml_file) = &puml_file {
+                queue.extend(puml_file.referenced_files.iter().cloned());
             }
+            validator.puml_files.push(puml_file);
         }
 
         validator
     }
-    pub fn validate(&mut self) {
-        for puml_file in self.puml_files.iter_mut() {
-            puml_file.validate();
+    pub fn validate(&mut self, profile: Profile) {
+        for puml_file in self.puml_files.iter_mut().flatten() {
+            puml_file.validate(profile);
         }
     }
     pub fn print_errors(&self) {
         for puml_file in self.puml_files.iter() {
-            puml_file.print_errors();
+            match puml_file {
+                Ok(puml_file) => puml_file.print_errors(),
+                Err(err) => print_file_error(err),
+            }
+        }
+    }
+    pub fn summary(&self) -> Summary {
+        Summary {
+            files_skipped: self.puml_files.iter().filter(|f| f.is_err()).count(),
+            section_errors: self
+                .puml_files
+                .iter()
+                .flatten()
+                .map(|puml_file| puml_file.section_errors.len())
+                .sum(),
+            // warnings do not fail a run
+            errors: self
+                .puml_files
+                .iter()
+                .flatten()
+                .flat_map(|puml_file| puml_file.pumls.iter())
+                .flat_map(|puml| puml.errors.iter())
+                .filter(|err| err.rule.severity() == Severity::Error)
+                .count(),
         }
     }
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.reports()
+            .iter()
+            .flat_map(|report| report.diagnostics().cloned())
+            .collect()
+    }
+    pub fn reports(&self) -> Vec<Report> {
+        self.puml_files
+            .iter()
+            .map(|puml_file| match puml_file {
+                Ok(puml_file) => puml_file.report(),
+                Err(err) => Report::from_error(err),
+            })
+            .collect()
+    }
+}
+
+fn print_diagnostic(diagnostic: &Diagnostic, suffix: &str) {
+    println!(
+        "{} {} {}",
+        format!(
+            "{}:{}:{}:",
+            diagnostic.file, diagnostic.span.line, diagnostic.span.column
+        )
+        .color("grey"),
+        diagnostic.source.trim().bold(),
+        format!("<- {}{}", diagnostic.message, suffix).color(match diagnostic.severity {
+            Severity::Error => "red",
+            Severity::Warning => "yellow",
+        })
+    );
+    for related in diagnostic.related.iter() {
+        println!(
+            "    {} {}",
+            format!(
+                "{}:{}:{}:",
+                diagnostic.file, related.span.line, related.span.column
+            )
+            .color("grey"),
+            related.message
+        );
+    }
+}
+
+fn print_file_error(err: &Error) {
+    println!("In file {}:", file_name(err.path()));
+    println!();
+    print_diagnostic(&error_diagnostic(err), ", skipping file");
+    println!();
+}
+
+/// Checks PlantUML source text. `@startuml` ... `@enduml` sections are
+/// checked like in a `.puml` file, source without `@startuml` is checked as
+/// a single diagram. Diagnostics name the file `<source>`.
+pub fn check_source(source: &str) -> Vec<Diagnostic> {
+    check_source_with_profile(source, Profile::Default)
+}
+
+/// `check_source` running the checks of `profile`.
+pub fn check_source_with_profile(source: &str, profile: Profile) -> Vec<Diagnostic> {
+    let lines: Vec<&str> = source.lines().collect();
+    let path = Path::new("<source>");
+    let mut section_errors = Vec::new();
+    let mut pumls = read_embedded_pumls(path, 0, &lines, &mut section_errors);
+
+    let mut diagnostics: Vec<Diagnostic> = section_errors.iter().map(error_diagnostic).collect();
+    for puml in pumls.iter_mut() {
+        puml.validate(profile);
+        diagnostics.extend(puml.diagnostics("<source>"));
+    }
+    diagnostics
+}
+
+/// Parses PlantUML source text like `check_source` into one syntax tree per
+/// diagram. Syntax errors are recovered from, `check_source` reports them.
+pub fn parse_source(source: &str) -> Vec<Diagram> {
+    let lines: Vec<&str> = source.lines().collect();
+    let path = Path::new("<source>");
+    read_embedded_pumls(path, 0, &lines, &mut Vec::new())
+        .iter()
+        .map(|puml| Diagram {
+            starting_line: puml.starting_line,
+            statements: puml.parse(&mut Vec::new()),
+        })
+        .collect()
+}
+
+/// Checks a single file, which may be a PlantUML, Markdown or AsciiDoc file.
+/// Files referenced by the file are not followed.
+pub fn check_path(path: &Path) -> Result<Report, Error> {
+    check_path_with_profile(path, Profile::Default)
+}
+
+/// `check_path` running the checks of `profile`.
+pub fn check_path_with_profile(path: &Path, profile: Profile) -> Result<Report, Error> {
+    let mut puml_file = PumlFile::new(path)?;
+    puml_file.validate(profile);
+    Ok(puml_file.report())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_stdin_input_after_the_given_filename() {
+        let source = "# Flow\n```plantuml\n  bad;\n```\n";
+        let mut puml_file =
+            PumlFile::from_reader(Path::new("docs/flow.md"), source.as_bytes()).unwrap();
+        puml_file.validate(Profile::Default);
+        assert_eq!(puml_file.filename, "flow.md");
+        let report = puml_file.report();
+        assert_eq!(report.file, "docs/flow.md");
+        assert_eq!(report.blocks[0].diagnostics[0].rule, Rule::MissingColon);
+        assert_eq!(report.blocks[0].diagnostics[0].span.line, 3);
+    }
+
+    #[test]
+    fn resyncs_after_unbalanced_sections() {
+        let source = "@enduml\n@startuml\n:a;\n@startuml\n:b;\n@enduml\n@startuml\n:c;";
+        let lines = source.lines().enumerate().map(|(i, l)| (i + 1, l));
+        let mut errors = Vec::new();
+        let pumls = read_pumls(Path::new("a.puml"), lines, &mut errors);
+
+        let sections: Vec<(usize, &[String])> = pumls
+            .iter()
+            .map(|puml| (puml.starting_line, &puml.lines[..]))
+            .collect();
+        assert_eq!(
+            sections,
+            [
+                (2, &[":a;".to_owned()][..]),
+                (4, &[":b;".to_owned()][..]),
+                (7, &[":c;".to_owned()][..]),
+            ]
+        );
+        let kinds: Vec<(usize, SectionErrorKind)> = errors
+            .iter()
+            .map(|err| match err {
+                Error::Sections { line, kind, .. } => (*line, *kind),
+                err => panic!("expected a section error, found {:?}", err),
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (1, SectionErrorKind::OrphanEnduml),
+                (4, SectionErrorKind::NestedStartuml { previous: 2 }),
+                (7, SectionErrorKind::MissingEnduml),
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_section_errors_apart_from_lint_errors() {
+        let source = "@enduml\n@startuml\nstart\n  bad;\n@enduml\n@startuml\n:no start;\n@enduml";
+        let mut validator = PumlValidator {
+            puml_files: vec![Ok(PumlFile::from_source(Path::new("a.puml"), source))],
+        };
+        validator.validate(Profile::Default);
+        let summary = validator.summary();
+        assert_eq!(summary.files_skipped, 0);
+        assert_eq!(summary.section_errors, 1);
+        assert_eq!(summary.errors, 1, "warnings are not counted");
+    }
+
+    #[test]
+    fn runs_the_checks_of_the_given_profile() {
+        let source = "@startuml\nstart\nif (a) then\n  stop\nelse\n  :b;\nendif\n@enduml";
+        assert!(check_source(source).is_empty());
+        let strict = check_source_with_profile(source, Profile::Strict);
+        assert_eq!(strict.len(), 1);
+        assert_eq!(strict[0].rule, Rule::MissingStop);
+        assert_eq!(strict[0].severity, Severity::Warning);
+    }
 }
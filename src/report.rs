@@ -0,0 +1,538 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::env;
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+use crate::puml_validator::{Diagnostic, Report, Rule, Severity};
+
+/// Version of the JSON document layout, bumped on incompatible changes.
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    Text,
+    Json,
+    Sarif,
+    Junit,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "sarif" => Ok(Format::Sarif),
+            "junit" => Ok(Format::Junit),
+            _ => Err(format!(
+                "unknown format '{}', expected one of: text, json, sarif, junit",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    version: u32,
+    diagnostics: &'a [Diagnostic],
+}
+
+pub fn print_json(diagnostics: &[Diagnostic]) {
+    let report = JsonReport {
+        version: JSON_SCHEMA_VERSION,
+        diagnostics,
+    };
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+}
+
+fn sarif_level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+    }
+}
+
+/// Percent-encodes everything but unreserved URI characters.
+fn percent_encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// The `/`-separated, percent-encoded URI reference of `path`, without the
+/// root of absolute paths.
+fn uri_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Prefix(prefix) => Some(prefix.as_os_str().to_string_lossy().into_owned()),
+            Component::RootDir | Component::CurDir => None,
+            Component::ParentDir => Some("..".to_owned()),
+            Component::Normal(name) => Some(percent_encode(&name.to_string_lossy())),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The `file://` URL of the directory `path`, with a trailing `/`.
+fn directory_url(path: &Path) -> String {
+    match uri_path(path).as_str() {
+        "" => "file:///".to_owned(),
+        path => format!("file:///{}/", path),
+    }
+}
+
+/// The SARIF artifact location of `file`: relative to the working directory
+/// and based on `%SRCROOT%` if possible, an absolute `file://` URL otherwise.
+fn sarif_artifact(file: &str) -> Value {
+    let path = Path::new(file);
+    let relative = if path.is_absolute() {
+        env::current_dir()
+            .ok()
+            .and_then(|cwd| path.strip_prefix(cwd).ok().map(Path::to_path_buf))
+    } else {
+        Some(path.to_path_buf())
+    };
+    match relative {
+        Some(relative) => json!({ "uri": uri_path(&relative), "uriBaseId": "%SRCROOT%" }),
+        None => json!({ "uri": format!("file:///{}", uri_path(path)) }),
+    }
+}
+
+fn sarif_result(diagnostic: &Diagnostic) -> Value {
+    let rule_index = Rule::ALL
+        .iter()
+        .position(|rule| *rule == diagnostic.rule)
+        .unwrap();
+    let artifact = sarif_artifact(&diagnostic.file);
+
+    let mut result = json!({
+        "ruleId": diagnostic.rule.id(),
+        "ruleIndex": rule_index,
+        "level": sarif_level(diagnostic.severity),
+        "message": { "text": diagnostic.message },
+        "locations": [{
+            "physicalLocation": {
+                "artifactLocation": artifact,
+                "region": {
+                    "startLine": diagnostic.span.line,
+                    "startColumn": diagnostic.span.column,
+                    "endColumn": diagnostic.span.end_column,
+                    "snippet": { "text": diagnostic.source },
+                },
+            },
+        }],
+    });
+
+    if !diagnostic.related.is_empty() {
+        let related: Vec<Value> = diagnostic
+            .related
+            .iter()
+            .map(|related| {
+                json!({
+                    "physicalLocation": {
+                        "artifactLocation": artifact,
+                        "region": {
+                            "startLine": related.span.line,
+                            "startColumn": related.span.column,
+                            "endColumn": related.span.end_column,
+                        },
+                    },
+                    "message": { "text": related.message },
+                })
+            })
+            .collect();
+        result["relatedLocations"] = Value::Array(related);
+    }
+
+    if let Some(fix) = &diagnostic.fix {
+        result["fixes"] = json!([{
+            "description": { "text": fix.description },
+            "artifactChanges": [{
+                "artifactLocation": artifact,
+                "replacements": [{
+                    "deletedRegion": {
+                        "startLine": fix.span.line,
+                        "startColumn": fix.span.column,
+                        "endColumn": fix.span.end_column,
+                    },
+                    "insertedContent": { "text": fix.replacement },
+                }],
+            }],
+        }]);
+    }
+
+    result
+}
+
+pub fn print_sarif(diagnostics: &[Diagnostic]) {
+    let rules: Vec<Value> = Rule::ALL
+        .iter()
+        .map(|rule| {
+            json!({
+                "id": rule.id(),
+                "shortDescription": { "text": rule.description() },
+                "defaultConfiguration": { "level": sarif_level(rule.severity()) },
+            })
+        })
+        .collect();
+
+    let mut sarif = json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                },
+            },
+            "results": diagnostics.iter().map(sarif_result).collect::<Vec<Value>>(),
+        }],
+    });
+    if let Ok(cwd) = env::current_dir() {
+        sarif["runs"][0]["originalUriBaseIds"] = json!({
+            "%SRCROOT%": { "uri": directory_url(&cwd) },
+        });
+    }
+    println!("{}", serde_json::to_string_pretty(&sarif).unwrap());
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The text of `diagnostic` in a JUnit element, with its related locations.
+fn junit_text(file: &str, diagnostic: &Diagnostic) -> String {
+    let related: String = diagnostic
+        .related
+        .iter()
+        .map(|related| {
+            format!(
+                "&#10;{}:{}:{}: {}",
+                file,
+                related.span.line,
+                related.span.column,
+                xml_escape(&related.message)
+            )
+        })
+        .collect();
+    format!(
+        "{}:{}:{}: {}&#10;{}{}",
+        file,
+        diagnostic.span.line,
+        diagnostic.span.column,
+        xml_escape(&diagnostic.message),
+        xml_escape(&diagnostic.source),
+        related
+    )
+}
+
+fn print_junit_diagnostic(element: &str, file: &str, diagnostic: &Diagnostic) {
+    println!(
+        r#"      <{} message="{}" type="{}">{}</{}>"#,
+        element,
+        xml_escape(&diagnostic.message),
+        diagnostic.rule.id(),
+        junit_text(file, diagnostic),
+        element
+    );
+}
+
+/// Prints the errors of a test case as `element`s and its warnings, which
+/// do not fail it, as `<system-out>`.
+fn print_junit_case(element: &str, file: &str, diagnostics: &[Diagnostic]) {
+    let (errors, warnings): (Vec<&Diagnostic>, Vec<&Diagnostic>) = diagnostics
+        .iter()
+        .partition(|diagnostic| diagnostic.severity == Severity::Error);
+    for diagnostic in errors {
+        print_junit_diagnostic(element, file, diagnostic);
+    }
+    if !warnings.is_empty() {
+        let text: Vec<String> = warnings
+            .iter()
+            .map(|diagnostic| format!("warning: {}", junit_text(file, diagnostic)))
+            .collect();
+        println!("      <system-out>{}</system-out>", text.join("&#10;"));
+    }
+}
+
+fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error)
+}
+
+/// Whether a file's diagnostics mean it could not be checked at all.
+fn is_unchecked(file: &Report) -> bool {
+    file.file_diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.rule == Rule::Io)
+}
+
+/// The counts of the `<testsuite>` of a file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct JunitCounts {
+    tests: usize,
+    failures: usize,
+    errors: usize,
+}
+
+impl JunitCounts {
+    fn of(file: &Report) -> JunitCounts {
+        let errors = usize::from(is_unchecked(file));
+        JunitCounts {
+            tests: file.blocks.len() + usize::from(!file.file_diagnostics.is_empty()),
+            failures: usize::from(has_errors(&file.file_diagnostics)) - errors
+                + file
+                    .blocks
+                    .iter()
+                    .filter(|block| has_errors(&block.diagnostics))
+                    .count(),
+            errors,
+        }
+    }
+
+    fn add(self, other: JunitCounts) -> JunitCounts {
+        JunitCounts {
+            tests: self.tests + other.tests,
+            failures: self.failures + other.failures,
+            errors: self.errors + other.errors,
+        }
+    }
+}
+
+/// Prints one `<testsuite>` per file and one `<testcase>` per `@startuml`
+/// block, with a `<failure>` for every error in the block and warnings in
+/// its `<system-out>`. Diagnostics about the file as a whole go to an extra
+/// test case named after the file, as an `<error>` if the file could not be
+/// checked.
+pub fn print_junit(files: &[Report]) {
+    let total = files
+        .iter()
+        .map(JunitCounts::of)
+        .fold(JunitCounts::default(), JunitCounts::add);
+
+    println!(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    println!(
+        r#"<testsuites name="{}" tests="{}" failures="{}" errors="{}">"#,
+        env!("CARGO_PKG_NAME"),
+        total.tests,
+        total.failures,
+        total.errors
+    );
+    for file in files {
+        let name = xml_escape(&file.file);
+        let counts = JunitCounts::of(file);
+        println!(
+            r#"  <testsuite name="{}" tests="{}" failures="{}" errors="{}">"#,
+            name, counts.tests, counts.failures, counts.errors
+        );
+        if !file.file_diagnostics.is_empty() {
+            let element = if is_unchecked(file) {
+                "error"
+            } else {
+                "failure"
+            };
+            println!(r#"    <testcase name="{}" classname="{}">"#, name, name);
+            print_junit_case(element, &name, &file.file_diagnostics);
+            println!("    </testcase>");
+        }
+        for block in file.blocks.iter() {
+            println!(
+                r#"    <testcase name="{}:{}" classname="{}">"#,
+                name, block.starting_line, name
+            );
+            print_junit_case("failure", &name, &block.diagnostics);
+            println!("    </testcase>");
+        }
+        println!("  </testsuite>");
+    }
+    println!("</testsuites>");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::puml_validator::{check_source, BlockDiagnostics, PumlValidator};
+
+    /// A diagram with a missing ':' on line 3 and an activity on line 4
+    /// that is never terminated.
+    const SOURCE: &str = "@startuml\nstart\n  bad;\n:open\nstop\n@enduml";
+
+    #[test]
+    fn parses_format_names() {
+        assert_eq!("json".parse(), Ok(Format::Json));
+        assert_eq!("junit".parse(), Ok(Format::Junit));
+        assert!("xml".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn writes_versioned_json_with_flat_locations() {
+        let diagnostics = check_source(SOURCE);
+        let report = serde_json::to_value(JsonReport {
+            version: JSON_SCHEMA_VERSION,
+            diagnostics: &diagnostics,
+        })
+        .unwrap();
+        assert_eq!(report["version"], JSON_SCHEMA_VERSION);
+        assert_eq!(
+            report["diagnostics"][0],
+            json!({
+                "file": "<source>",
+                "line": 3,
+                "column": 3,
+                "end_column": 7,
+                "rule": "missing-colon",
+                "severity": "error",
+                "message": "missing ':' at the beginning of the activity",
+                "source": "  bad;",
+                "fix": {
+                    "description": "insert ':'",
+                    "line": 3,
+                    "column": 3,
+                    "end_column": 3,
+                    "replacement": ":",
+                },
+            })
+        );
+        assert_eq!(
+            report["diagnostics"][1]["related"],
+            json!([{
+                "line": 5,
+                "column": 5,
+                "end_column": 5,
+                "message": "the diagram ends here",
+            }])
+        );
+    }
+
+    #[test]
+    fn encodes_uris() {
+        assert_eq!(percent_encode("a b#ä.puml"), "a%20b%23%C3%A4.puml");
+        assert_eq!(uri_path(Path::new("./docs/a b.puml")), "docs/a%20b.puml");
+        assert_eq!(uri_path(Path::new("../x.puml")), "../x.puml");
+        assert_eq!(directory_url(Path::new("/")), "file:///");
+        assert_eq!(
+            directory_url(Path::new("/src/my repo")),
+            "file:///src/my%20repo/"
+        );
+    }
+
+    #[test]
+    fn bases_artifacts_on_the_source_root_if_possible() {
+        assert_eq!(
+            sarif_artifact("docs/a b.puml"),
+            json!({ "uri": "docs/a%20b.puml", "uriBaseId": "%SRCROOT%" })
+        );
+        let inside = env::current_dir().unwrap().join("docs").join("x.puml");
+        assert_eq!(
+            sarif_artifact(&inside.display().to_string()),
+            json!({ "uri": "docs/x.puml", "uriBaseId": "%SRCROOT%" })
+        );
+        assert_eq!(
+            sarif_artifact("/nonexistent root/x.puml"),
+            json!({ "uri": "file:///nonexistent%20root/x.puml" })
+        );
+    }
+
+    #[test]
+    fn writes_sarif_regions_related_locations_and_fixes() {
+        let diagnostics = check_source(SOURCE);
+        let result = sarif_result(&diagnostics[1]);
+        assert_eq!(result["ruleId"], "missing-semicolon");
+        assert_eq!(
+            Rule::ALL[result["ruleIndex"].as_u64().unwrap() as usize],
+            Rule::MissingSemicolon
+        );
+        assert_eq!(result["level"], "error");
+        assert_eq!(
+            result["locations"][0]["physicalLocation"]["region"],
+            json!({
+                "startLine": 4,
+                "startColumn": 1,
+                "endColumn": 6,
+                "snippet": { "text": ":open" },
+            })
+        );
+        assert_eq!(
+            result["relatedLocations"][0]["physicalLocation"]["region"],
+            json!({ "startLine": 5, "startColumn": 5, "endColumn": 5 })
+        );
+        assert_eq!(
+            result["fixes"][0]["artifactChanges"][0]["replacements"],
+            json!([{
+                "deletedRegion": { "startLine": 4, "startColumn": 6, "endColumn": 6 },
+                "insertedContent": { "text": ";" },
+            }])
+        );
+        assert!(sarif_result(&diagnostics[0])
+            .get("relatedLocations")
+            .is_none());
+    }
+
+    #[test]
+    fn counts_only_blocks_with_errors_as_failures() {
+        let block = |source: &str| BlockDiagnostics {
+            starting_line: 1,
+            diagnostics: check_source(source),
+        };
+        let checked = Report {
+            file: "a.puml".to_owned(),
+            file_diagnostics: Vec::new(),
+            blocks: vec![
+                block(SOURCE),
+                block("@startuml\n:no start;\n@enduml"),
+                block("@startuml\nstart\nstop\n@enduml"),
+            ],
+        };
+        assert_eq!(
+            JunitCounts::of(&checked),
+            JunitCounts {
+                tests: 3,
+                failures: 1,
+                errors: 0,
+            }
+        );
+
+        let unreadable = PumlValidator::new(vec!["/nonexistent/a.puml".into()], None).reports();
+        assert_eq!(
+            JunitCounts::of(&unreadable[0]),
+            JunitCounts {
+                tests: 1,
+                failures: 0,
+                errors: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn escapes_junit_text_with_related_locations() {
+        let diagnostics = check_source(SOURCE);
+        assert_eq!(
+            junit_text("a.puml", &diagnostics[1]),
+            "a.puml:4:1: activity is never terminated with &apos;;&apos; or an SDL terminator\
+             &#10;:open&#10;a.puml:5:5: the diagram ends here"
+        );
+    }
+}